@@ -0,0 +1,198 @@
+//! Local files given to the model along with a question.
+
+use std::{
+    fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+use globset::GlobBuilder;
+use ignore::WalkBuilder;
+
+use crate::{context, error::SkyError};
+
+/// The most files a directory or glob stands for.
+pub const MAX_FILES: usize = 1000;
+
+/// The most entries looked at for a directory or glob, so `@/` doesn't go through the
+/// whole disk.
+const MAX_WALK: usize = 20_000;
+
+/// The files `pattern` stands for: a file, every file under a directory, or those matching
+/// a glob like `src/**/*.rs`. Hidden files and those ignored by git are left out of the
+/// last two.
+pub fn expand(pattern: &str) -> Result<Vec<PathBuf>, SkyError> {
+    let path = Path::new(pattern);
+    let mut files = vec![];
+
+    if !pattern.contains(['*', '?', '[']) {
+        match fs::metadata(path) {
+            Ok(meta) if meta.is_dir() => files = walk_all(path, |_| true),
+            Ok(_) => files.push(path.to_path_buf()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+    } else {
+        let base = glob_base(pattern);
+        let rest = path.strip_prefix(&base).unwrap_or(path);
+
+        if base.is_dir() {
+            let glob = GlobBuilder::new(&slashed(rest))
+                .literal_separator(true)
+                .build()
+                .map_err(|_| SkyError::NoSuchFile(pattern.to_string()))?
+                .compile_matcher();
+            files = walk_all(&base, |file| {
+                file.strip_prefix(&base)
+                    .is_ok_and(|rel| glob.is_match(slashed(rel)))
+            });
+        }
+    }
+
+    match files.is_empty() {
+        true => Err(SkyError::NoSuchFile(pattern.to_string())),
+        false => Ok(files),
+    }
+}
+
+/// The directory a glob like `src/**/*.rs` searches, the part before the first component
+/// with a wildcard in it, or `.` when there is none.
+pub(crate) fn glob_base(pattern: &str) -> PathBuf {
+    let base: PathBuf = Path::new(pattern)
+        .components()
+        .take_while(|c| !c.as_os_str().to_string_lossy().contains(['*', '?', '[']))
+        .collect();
+    match base.as_os_str().is_empty() {
+        true => PathBuf::from("."),
+        false => base,
+    }
+}
+
+/// Takes the files mentioned like `@src/lib.rs` out of `text`, leaving their paths
+/// without the `@`. Mentions of paths that don't exist are left alone.
+pub fn mentioned(text: &str) -> (String, Vec<PathBuf>) {
+    let mut files = vec![];
+    let text = text
+        .split_inclusive(char::is_whitespace)
+        .map(|word| match word.trim_end().strip_prefix('@') {
+            Some(pattern) if !pattern.is_empty() => match expand(pattern) {
+                Ok(found) => {
+                    files.extend(found);
+                    word[1..].to_string()
+                }
+                Err(_) => word.to_string(),
+            },
+            _ => word.to_string(),
+        })
+        .collect();
+
+    (text, files)
+}
+
+/// Puts `files` before `question`, each in a fenced block labelled with its path, for as
+/// long as they fit in `budget` tokens. Gives back the whole of it along with notes on
+/// the files that were cut short, left out or couldn't be read.
+pub fn attach(files: &[PathBuf], question: &str, budget: usize) -> (String, Vec<String>) {
+    let mut text = String::new();
+    let mut notes = vec![];
+    let mut left = budget;
+
+    for file in files {
+        if left == 0 {
+            notes.push(format!(
+                "left out {}, the files before it used up the budget of {budget} tokens",
+                file.display()
+            ));
+            continue;
+        }
+
+        let content = match fs::read(file) {
+            Ok(bytes) if bytes.iter().take(8000).any(|&b| b == 0) => {
+                notes.push(format!("left out {}, it isn't text", file.display()));
+                continue;
+            }
+            Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+            Err(e) => {
+                notes.push(format!("left out {}: {e}", file.display()));
+                continue;
+            }
+        };
+
+        let tokens = context::count_text(&content);
+        let content = match tokens > left {
+            true => {
+                notes.push(format!(
+                    "cut {} short, it takes about {tokens} tokens and only {left} were left",
+                    file.display()
+                ));
+                cut(&content, left)
+            }
+            false => content,
+        };
+        left = left.saturating_sub(tokens);
+
+        text.push_str(&fenced(file, &content));
+        text.push('\n');
+    }
+
+    text.push_str(question);
+    (text, notes)
+}
+
+/// The start of `content`, up to about `tokens` tokens, ending on a whole line where possible.
+fn cut(content: &str, tokens: usize) -> String {
+    let mut kept = String::new();
+    for line in content.split_inclusive('\n') {
+        if context::count_text(&kept) + context::count_text(line) > tokens {
+            break;
+        }
+        kept.push_str(line);
+    }
+    kept.push_str("\n... (cut short)\n");
+    kept
+}
+
+/// `content` in a fence longer than any run of backticks in it, labelled with `file`.
+fn fenced(file: &Path, content: &str) -> String {
+    let longest = content.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest.max(2) + 1);
+    let lang = file
+        .extension()
+        .map(|ext| ext.to_string_lossy())
+        .unwrap_or_default();
+
+    format!(
+        "{}:\n{fence}{lang}\n{}\n{fence}\n",
+        slashed(file),
+        content.trim_end()
+    )
+}
+
+/// Every file under `dir` that `keep` keeps, in order, skipping hidden ones, those git
+/// would ignore and those that can't be read, and not following links to directories.
+/// Stops at [`MAX_FILES`] files, or after looking at [`MAX_WALK`] entries.
+fn walk_all(dir: &Path, keep: impl Fn(&Path) -> bool) -> Vec<PathBuf> {
+    WalkBuilder::new(dir)
+        .require_git(false)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build()
+        .take(MAX_WALK)
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            entry
+                .file_type()
+                .is_some_and(|t| t.is_file() || (t.is_symlink() && entry.path().is_file()))
+        })
+        .map(|entry| entry.into_path())
+        .filter(|path| keep(path))
+        .take(MAX_FILES)
+        .collect()
+}
+
+/// `path` with its components joined by `/`, whatever the platform.
+pub(crate) fn slashed(path: &Path) -> String {
+    path.components()
+        .filter(|c| *c != Component::CurDir)
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
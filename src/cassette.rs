@@ -0,0 +1,143 @@
+//! Recording what goes to and comes back from the API, and playing it back later.
+//!
+//! A cassette is a directory holding, for the Nth request, `NNNN.request.json` with the
+//! request as it was sent, and `NNNN.response` with the raw body of the response to it.
+//! When the API turned the request down, `NNNN.status` holds the status it answered with.
+//! Requests that never got an answer, like those to an unreachable API, have no response.
+
+use std::{
+    cell::Cell,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::PathBuf,
+};
+
+use serde_json::json;
+
+use crate::{error::SkyError, provider::HttpRequest};
+
+/// The body of a response, or the status and body of one turning the request down.
+pub type Exchange = Result<Box<dyn Read>, (u16, String)>;
+
+pub struct Cassette {
+    dir: PathBuf,
+    replay: bool,
+    /// The number of the next request, counting from 1.
+    next: Cell<usize>,
+}
+
+impl Cassette {
+    /// Records into `dir`, after any requests already recorded there.
+    pub fn recording_into(dir: impl Into<PathBuf>) -> Result<Self, SkyError> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        let recorded = fs::read_dir(&dir)?
+            .filter_map(Result::ok)
+            .filter(|e| e.file_name().to_string_lossy().ends_with(".request.json"))
+            .count();
+
+        Ok(Self {
+            dir,
+            replay: false,
+            next: Cell::new(recorded + 1),
+        })
+    }
+
+    /// Plays back what was recorded in `dir`, from the first request on.
+    pub fn replaying_from(dir: impl Into<PathBuf>) -> Result<Self, SkyError> {
+        let dir = dir.into();
+        if !dir.is_dir() {
+            return Err(SkyError::NoSuchFile(dir.to_string_lossy().into_owned()));
+        }
+
+        Ok(Self {
+            dir,
+            replay: true,
+            next: Cell::new(1),
+        })
+    }
+
+    pub fn replaying(&self) -> bool {
+        self.replay
+    }
+
+    /// The recorded response to the next request, or the error it was turned down with.
+    pub fn play(&self) -> Result<Box<dyn Read>, SkyError> {
+        let n = self.next.replace(self.next.get() + 1);
+        let path = self.dir.join(format!("{n:04}.response"));
+
+        if let Ok(status) = fs::read_to_string(self.dir.join(format!("{n:04}.status"))) {
+            let code = status.trim().parse().map_err(|_| {
+                SkyError::InvalidConfig(format!("{n:04}.status doesn't hold a status: {status}"))
+            })?;
+            return Err(SkyError::status(code, &fs::read_to_string(path)?));
+        }
+
+        match File::open(path) {
+            Ok(file) => Ok(Box::new(file)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Err(SkyError::CassetteEnded {
+                dir: self.dir.to_string_lossy().into_owned(),
+                request: n,
+            }),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Writes down `req`, leaving out any credentials, then has `send` send it and hands
+    /// back the response, writing it down as it is read, or the error it was turned down
+    /// with, writing down the status and body.
+    pub fn record(
+        &self,
+        req: &HttpRequest,
+        send: impl FnOnce() -> Result<Exchange, SkyError>,
+    ) -> Result<Box<dyn Read>, SkyError> {
+        let n = self.next.replace(self.next.get() + 1);
+
+        let headers: serde_json::Map<_, _> = req
+            .headers
+            .iter()
+            .map(|(name, value)| match secret(name) {
+                true => (name.to_string(), json!("<redacted>")),
+                false => (name.to_string(), json!(value)),
+            })
+            .collect();
+        let request = json!({ "url": req.url, "headers": headers, "body": req.body });
+        fs::write(
+            self.dir.join(format!("{n:04}.request.json")),
+            serde_json::to_string_pretty(&request)?,
+        )?;
+
+        let response = self.dir.join(format!("{n:04}.response"));
+        match send()? {
+            Ok(res) => {
+                let copy = File::create(response)?;
+                Ok(Box::new(Tee { res, copy }))
+            }
+            Err((code, body)) => {
+                fs::write(self.dir.join(format!("{n:04}.status")), format!("{code}\n"))?;
+                fs::write(response, &body)?;
+                Err(SkyError::status(code, &body))
+            }
+        }
+    }
+}
+
+/// Whether the header called `name` carries credentials.
+fn secret(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    name == "authorization" || name.contains("key")
+}
+
+/// A response that is copied as it is read.
+struct Tee {
+    res: Box<dyn Read>,
+    copy: File,
+}
+
+impl Read for Tee {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.res.read(buf)?;
+        self.copy.write_all(&buf[..read])?;
+        Ok(read)
+    }
+}
@@ -0,0 +1,222 @@
+//! Shell commands suggested by the model for a task, explained and run once the user agrees.
+
+use std::{
+    env,
+    fmt::Display,
+    io::{stderr, Read, Write},
+    process::{Command, ExitStatus, Stdio},
+    thread,
+};
+
+use serde::Deserialize;
+
+use crate::{config::Persona, error::SkyError, tools, Chat};
+
+/// How many times a failed command is handed back to the model to fix.
+const MAX_FIXES: u32 = 3;
+
+/// Most of the failed command's stderr handed back to the model, in bytes.
+const MAX_STDERR: usize = 4000;
+
+/// A command for the task, in the shape the model is asked to answer in.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Suggestion {
+    pub command: String,
+    pub explanation: String,
+    pub danger: Danger,
+    /// What could go wrong, when it isn't safe.
+    #[serde(default)]
+    pub risk: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Danger {
+    /// Only looks at things.
+    Safe,
+    /// Changes things that can be put back.
+    Caution,
+    /// Changes things for good, or reaches beyond the user's files.
+    Dangerous,
+}
+
+impl Display for Danger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let danger = match self {
+            Danger::Safe => "safe",
+            Danger::Caution => "caution",
+            Danger::Dangerous => "dangerous",
+        };
+        write!(f, "{danger}")
+    }
+}
+
+/// Parts of commands that are dangerous whatever the model thinks of them.
+const DANGEROUS: &[&str] = &[
+    "rm -r",
+    "rm -R",
+    "rm -f",
+    "sudo ",
+    "mkfs",
+    "dd ",
+    "> /dev/",
+    "chmod -R",
+    "chown -R",
+    ":(){",
+    "shred ",
+    "git push -f",
+    "git push --force",
+    "git reset --hard",
+    "git clean",
+];
+
+impl Suggestion {
+    /// Takes the model at its word on how dangerous the command is, unless it does
+    /// something sky knows better than to call safe.
+    fn checked(mut self) -> Self {
+        if self.danger < Danger::Dangerous {
+            if let Some(part) = DANGEROUS.iter().find(|p| runs(&self.command, p)) {
+                self.danger = Danger::Dangerous;
+                if self.risk.is_empty() {
+                    self.risk = format!("it runs `{}`", part.trim());
+                }
+            }
+        }
+        self
+    }
+}
+
+/// Whether `part` is in `command` where a command of its own could start.
+fn runs(command: &str, part: &str) -> bool {
+    command.match_indices(part).any(|(i, _)| {
+        command[..i]
+            .chars()
+            .next_back()
+            .is_none_or(|c| c.is_whitespace() || ";|&(`".contains(c))
+    })
+}
+
+impl Display for Suggestion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "\n  {}\n", self.command)?;
+        writeln!(f, "{}", self.explanation.trim())?;
+        match self.risk.trim() {
+            "" => writeln!(f, "danger: {}", self.danger),
+            risk => writeln!(f, "danger: {}, {risk}", self.danger),
+        }
+    }
+}
+
+/// The shell commands are run in.
+fn shell() -> String {
+    env::var("SHELL")
+        .ok()
+        .filter(|shell| !shell.is_empty())
+        .unwrap_or_else(|| "sh".to_string())
+}
+
+fn system_prompt() -> String {
+    format!(
+        "You turn tasks into a single command for {shell} on {os}. Answer with nothing but a JSON object \
+         like {{\"command\": \"...\", \"explanation\": \"...\", \"danger\": \"safe\", \"risk\": \"\"}}, where \
+         explanation says briefly what the command and its parts do, danger is safe when the command \
+         only looks at things, caution when it changes things that can be put back, and dangerous when \
+         it changes things for good or reaches beyond the user's own files, and risk says what could go \
+         wrong when it isn't safe.",
+        shell = shell(),
+        os = env::consts::OS,
+    )
+}
+
+/// Asks the model for a command for `task`, shows it, and runs it if the user agrees,
+/// asking for a fix when it fails. Gives back how the last command run exited, if any was.
+pub fn run(chat: &mut dyn Chat, task: &str) -> Result<Option<ExitStatus>, SkyError> {
+    let persona = Persona {
+        system_prompt: system_prompt(),
+        ..chat.persona().clone()
+    };
+    chat.set_persona(persona);
+
+    let mut ask = task.to_string();
+    let mut last = None;
+
+    for fix in 0..=MAX_FIXES {
+        let suggestion = suggest(chat, ask)?;
+        print!("{suggestion}");
+
+        let question = match suggestion.danger {
+            Danger::Dangerous => "run it, dangerous as it is?",
+            _ => "run it?",
+        };
+        if !tools::confirm(question) {
+            break;
+        }
+
+        let (status, errors) = execute(&suggestion.command)?;
+        last = Some(status);
+        if status.success() {
+            break;
+        }
+
+        if fix == MAX_FIXES {
+            eprintln!("giving up after {MAX_FIXES} fixes");
+            break;
+        }
+        eprintln!("\nthe command failed with {status}, asking for a fix");
+        ask = format!(
+            "The command failed with {status}. Its stderr was:\n{}\nAnswer with a fixed command in the same JSON format.",
+            tail(&errors, MAX_STDERR)
+        );
+    }
+
+    Ok(last)
+}
+
+/// Asks the model for a command, reading it from the JSON object in the answer.
+fn suggest(chat: &mut dyn Chat, ask: String) -> Result<Suggestion, SkyError> {
+    let answer = chat.say(ask)?.to_string();
+
+    // Models like to wrap it in a code fence or say something around it anyway.
+    let json = match (answer.find('{'), answer.rfind('}')) {
+        (Some(start), Some(end)) if start < end => &answer[start..=end],
+        _ => answer.as_str(),
+    };
+    let suggestion: Suggestion = serde_json::from_str(json)?;
+
+    Ok(suggestion.checked())
+}
+
+/// Runs `command` in the user's shell, with its output going to the terminal as usual,
+/// giving back how it exited and what it wrote to stderr.
+fn execute(command: &str) -> Result<(ExitStatus, String), SkyError> {
+    let mut child = Command::new(shell())
+        .arg("-c")
+        .arg(command)
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let errors = child.stderr.take().map(|mut pipe| {
+        thread::spawn(move || {
+            let mut errors = vec![];
+            let mut buf = [0; 4096];
+            while let Ok(n @ 1..) = pipe.read(&mut buf) {
+                stderr().write_all(&buf[..n]).ok();
+                errors.extend_from_slice(&buf[..n]);
+            }
+            String::from_utf8_lossy(&errors).into_owned()
+        })
+    });
+
+    let status = child.wait()?;
+    let errors = errors.and_then(|e| e.join().ok()).unwrap_or_default();
+    Ok((status, errors))
+}
+
+/// The end of `text`, up to `max` bytes, which is where the error usually is.
+fn tail(text: &str, max: usize) -> &str {
+    let mut start = text.len().saturating_sub(max);
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    text[start..].trim()
+}
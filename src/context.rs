@@ -0,0 +1,28 @@
+use crate::Message;
+
+/// Estimates how many tokens `messages` take up in a chat completion request,
+/// following the per-message overhead Openai documents for its chat models.
+pub fn count_tokens(messages: &[Message]) -> usize {
+    let bpe = tiktoken_rs::cl100k_base_singleton();
+    let bpe = bpe.lock();
+
+    messages
+        .iter()
+        .map(|m| {
+            let calls: usize = m
+                .tool_calls
+                .iter()
+                .map(|call| bpe.encode_ordinary(&call.function.arguments).len())
+                .sum();
+            4 + bpe.encode_ordinary(&m.content).len() + calls
+        })
+        .sum::<usize>()
+        + 3
+}
+
+/// Estimates how many tokens `text` takes up on its own, like an answer does.
+pub fn count_text(text: &str) -> usize {
+    let bpe = tiktoken_rs::cl100k_base_singleton();
+    let bpe = bpe.lock();
+    bpe.encode_ordinary(text).len()
+}
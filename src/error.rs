@@ -0,0 +1,168 @@
+use std::{fmt::Display, io};
+
+use serde::Deserialize;
+
+/// The error the API describes in the body of a failed request.
+#[derive(Debug, Deserialize)]
+pub struct ApiError {
+    pub message: String,
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiErrorBody {
+    error: ApiError,
+}
+
+#[derive(Debug)]
+pub enum SkyError {
+    MissingApiKey,
+    Status { code: u16, error: Option<ApiError> },
+    Transport(Box<ureq::Transport>),
+    MalformedJson(serde_json::Error),
+    EmptyChoices,
+    NoSuchSession(String),
+    NoSuchFile(String),
+    BadSessionName(String),
+    InvalidConfig(String),
+    UnknownConfigKey(String),
+    NoSuchPersona(String),
+    NoSuchProfile(String),
+    NothingToAsk,
+    NoSuchChoice(usize),
+    NothingToContinue,
+    OverBudget { spent: f64, budget: f64 },
+    MockExhausted(String),
+    CassetteEnded { dir: String, request: usize },
+    UnknownCommand(String),
+    Io(io::Error),
+}
+
+impl Display for SkyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SkyError::MissingApiKey => write!(
+                f,
+                "no API key is configured, set one with `sky config --api-key <KEY>`"
+            ),
+            SkyError::Status {
+                code,
+                error: Some(error),
+            } => write!(f, "the API responded with {code}: {}", error.message),
+            SkyError::Status { code, error: None } => {
+                write!(f, "the API responded with status {code}")
+            }
+            SkyError::Transport(t) => write!(f, "couldn't reach the API: {t}"),
+            SkyError::MalformedJson(e) => {
+                write!(f, "couldn't make sense of the API's response: {e}")
+            }
+            SkyError::EmptyChoices => write!(f, "the API didn't send back any choices"),
+            SkyError::NoSuchSession(name) => write!(f, "there is no session named '{name}'"),
+            SkyError::NoSuchFile(pattern) => write!(f, "there are no files at '{pattern}'"),
+            SkyError::BadSessionName(name) if name.is_empty() => {
+                write!(f, "the session needs a name")
+            }
+            SkyError::BadSessionName(name) => write!(
+                f,
+                "'{name}' can't be used as a session name, it must not be empty, start with a dot or contain slashes"
+            ),
+            SkyError::InvalidConfig(reason) => write!(f, "invalid configuration: {reason}"),
+            SkyError::UnknownConfigKey(key) => write!(
+                f,
+                "there is no setting called '{key}', see `sky config keys` for those there are"
+            ),
+            SkyError::NoSuchPersona(name) => write!(f, "there is no persona named '{name}'"),
+            SkyError::NoSuchProfile(name) => write!(f, "there is no profile named '{name}'"),
+            SkyError::NothingToAsk => write!(f, "there is no question to ask"),
+            SkyError::NoSuchChoice(n) => write!(f, "there is no answer {n} to pick"),
+            SkyError::NothingToContinue => write!(f, "there is no answer to continue"),
+            SkyError::OverBudget { spent, budget } => write!(
+                f,
+                "${spent:.2} was spent today, which is all of the daily budget of ${budget:.2}, raise it with `sky config set daily_budget <DOLLARS>`"
+            ),
+            SkyError::MockExhausted(path) => {
+                write!(f, "the mock has no answers left in '{path}'")
+            }
+            SkyError::CassetteEnded { dir, request } => write!(
+                f,
+                "the cassette in '{dir}' has no response to request {request}"
+            ),
+            SkyError::UnknownCommand(name) => {
+                write!(f, "there is no /{name} command, try /help")
+            }
+            SkyError::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl SkyError {
+    /// The error for a response with status `code`, made out of its `body`.
+    pub fn status(code: u16, body: &str) -> Self {
+        SkyError::Status {
+            code,
+            error: serde_json::from_str::<ApiErrorBody>(body)
+                .ok()
+                .map(|body| body.error),
+        }
+    }
+
+    /// Exit status for a process that fails with this error, following sysexits.h.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            SkyError::NothingToAsk
+            | SkyError::BadSessionName(_)
+            | SkyError::UnknownCommand(_)
+            | SkyError::UnknownConfigKey(_)
+            | SkyError::NoSuchChoice(_)
+            | SkyError::NothingToContinue => 64,
+            SkyError::NoSuchSession(_)
+            | SkyError::NoSuchFile(_)
+            | SkyError::MockExhausted(_)
+            | SkyError::CassetteEnded { .. } => 66,
+            SkyError::OverBudget { .. } => 75,
+            SkyError::Transport(_) => 69,
+            SkyError::Status { code, .. } if *code == 429 || *code >= 500 => 69,
+            SkyError::Status { .. } | SkyError::MalformedJson(_) | SkyError::EmptyChoices => 76,
+            SkyError::Io(_) => 74,
+            SkyError::MissingApiKey
+            | SkyError::InvalidConfig(_)
+            | SkyError::NoSuchPersona(_)
+            | SkyError::NoSuchProfile(_) => 78,
+        }
+    }
+}
+
+impl std::error::Error for SkyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SkyError::Transport(t) => Some(t.as_ref()),
+            SkyError::MalformedJson(e) => Some(e),
+            SkyError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ureq::Error> for SkyError {
+    fn from(e: ureq::Error) -> Self {
+        match e {
+            ureq::Error::Status(code, res) => {
+                SkyError::status(code, &res.into_string().unwrap_or_default())
+            }
+            ureq::Error::Transport(t) => SkyError::Transport(Box::new(t)),
+        }
+    }
+}
+
+impl From<serde_json::Error> for SkyError {
+    fn from(e: serde_json::Error) -> Self {
+        SkyError::MalformedJson(e)
+    }
+}
+
+impl From<io::Error> for SkyError {
+    fn from(e: io::Error) -> Self {
+        SkyError::Io(e)
+    }
+}
@@ -0,0 +1,62 @@
+//! The HTTP client every request goes through, set up from the config.
+
+use std::{fs::File, io::BufReader, sync::Arc, time::Duration};
+
+use ureq::rustls;
+
+use crate::{
+    config::{without_credentials, Config},
+    error::SkyError,
+};
+
+pub fn agent(cfg: &Config) -> Result<ureq::Agent, SkyError> {
+    let mut builder = ureq::AgentBuilder::new()
+        .timeout_connect(Duration::from_secs(cfg.connect_timeout_secs))
+        .timeout_read(Duration::from_secs(cfg.read_timeout_secs));
+
+    if let Some(proxy) = &cfg.proxy {
+        let proxy = ureq::Proxy::new(proxy).map_err(|e| {
+            let proxy = without_credentials(proxy);
+            SkyError::InvalidConfig(format!("proxy '{proxy}' is unusable: {e}"))
+        })?;
+        builder = builder.proxy(proxy);
+    }
+
+    if let Some(path) = &cfg.ca_cert {
+        builder = builder.tls_config(Arc::new(tls_config(path)?));
+    }
+
+    Ok(builder.build())
+}
+
+/// The usual web roots, along with the certificates in the PEM file at `path`.
+///
+/// Built with the rustls ureq is built with, so the two always agree.
+fn tls_config(path: &str) -> Result<rustls::ClientConfig, SkyError> {
+    let unusable = |reason: String| {
+        SkyError::InvalidConfig(format!("CA certificate '{path}' is unusable: {reason}"))
+    };
+
+    let mut roots = rustls::RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+
+    let file = File::open(path).map_err(|e| unusable(e.to_string()))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| unusable(e.to_string()))?;
+    if certs.is_empty() {
+        return Err(unusable("there are no certificates in it".into()));
+    }
+    for cert in certs {
+        roots.add(cert).map_err(|e| unusable(e.to_string()))?;
+    }
+
+    Ok(rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .map_err(|e| unusable(e.to_string()))?
+    .with_root_certificates(roots)
+    .with_no_client_auth())
+}
@@ -1,20 +1,132 @@
+pub mod attach;
+pub mod cassette;
+pub mod cmd;
 pub mod config;
+pub mod context;
+pub mod error;
+pub mod http;
+pub mod mock;
+pub mod provider;
+pub mod repl;
+pub mod report;
+pub mod retry;
+pub mod screen;
+pub mod session;
+pub mod tools;
+pub mod usage;
 
-use std::io::{self, Write};
-use std::time::UNIX_EPOCH;
-use std::{fmt::Display, fs::File};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::io::{BufRead, BufReader, Cursor, Read};
+use std::path::PathBuf;
+use std::time::Duration;
 
-use config::Config;
-use serde::Deserialize;
+use cassette::{Cassette, Exchange};
+use config::{Config, ContextStrategy, Persona};
+use error::SkyError;
+use provider::{Event, Provider};
+use report::{Entry, Report, ReportFormat, Transcript};
+use serde::{Deserialize, Serialize};
+use session::Session;
+use tools::{Permit, ToolCall, Toolbox};
+use usage::{Spending, Usage};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+    /// What a tool the assistant called gave back.
+    Tool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message {
+    pub role: Role,
+    /// Null when the assistant only calls tools.
+    #[serde(default, deserialize_with = "null_as_empty")]
+    pub content: String,
+    /// The tools the assistant is calling.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    /// The call a tool message answers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+impl Message {
+    fn new(role: Role, content: impl Into<String>) -> Self {
+        Self {
+            role,
+            content: content.into(),
+            tool_calls: vec![],
+            tool_call_id: None,
+        }
+    }
+
+    pub fn system(content: impl Into<String>) -> Self {
+        Self::new(Role::System, content)
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self::new(Role::User, content)
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self::new(Role::Assistant, content)
+    }
+
+    /// What the tool called by `call_id` gave back.
+    pub fn tool(call_id: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            tool_call_id: Some(call_id.into()),
+            ..Self::new(Role::Tool, content)
+        }
+    }
+}
+
+fn null_as_empty<'de, D: serde::Deserializer<'de>>(de: D) -> Result<String, D::Error> {
+    Ok(Option::<String>::deserialize(de)?.unwrap_or_default())
+}
 
 #[derive(Debug, Deserialize)]
 pub struct Choice {
-    text: String,
+    message: Message,
+    /// Why the answer ended, like `stop`, or `length` when it ran into `max_tokens`.
+    #[serde(default)]
+    finish_reason: Option<String>,
+}
+
+impl Choice {
+    pub fn text(&self) -> &str {
+        self.message.content.trim()
+    }
+
+    /// Whether the answer was cut off for running out of tokens.
+    pub fn truncated(&self) -> bool {
+        self.finish_reason.as_deref() == Some("length")
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct AIResponse {
     choices: Vec<Choice>,
+    /// Missing from some local servers, in which case it gets estimated.
+    #[serde(default)]
+    usage: Option<Usage>,
+}
+
+impl AIResponse {
+    /// Whether the (first) answer was cut off for running out of tokens.
+    pub fn truncated(&self) -> bool {
+        self.choices.first().is_some_and(Choice::truncated)
+    }
+
+    /// Every answer given, when more than one was asked for.
+    pub fn choices(&self) -> impl Iterator<Item = &Choice> {
+        self.choices.iter()
+    }
 }
 
 impl Display for AIResponse {
@@ -23,133 +135,842 @@ impl Display for AIResponse {
             f,
             "{}",
             self.choices
-                .get(0)
-                .expect("should have gotten at least one choice from Openai")
-                .text
-                .trim()
+                .first()
+                .map(|c| c.message.content.trim())
+                .unwrap_or_default()
         )
     }
 }
 
 pub trait Chat {
-    fn say(&mut self, text: String) -> AIResponse;
+    fn say(&mut self, text: String) -> Result<AIResponse, SkyError>;
+
+    /// Like [`Chat::say`], but asks for `n` answers, keeping the first in the conversation
+    /// until another is picked with [`Chat::choose`].
+    fn say_choices(&mut self, text: String, n: u32) -> Result<AIResponse, SkyError>;
+
+    /// Keeps answer `i`, counting from 0, of the last question asked for several.
+    fn choose(&mut self, i: usize) -> Result<(), SkyError>;
+
+    /// Like [`Chat::say`], but hands every piece of the answer to `on_delta` as it arrives.
+    fn say_streaming(
+        &mut self,
+        text: String,
+        on_delta: &mut dyn FnMut(&str),
+    ) -> Result<AIResponse, SkyError>;
+
+    fn persona(&self) -> &Persona;
+
+    /// Carries on the conversation as someone else.
+    fn set_persona(&mut self, persona: Persona);
+
+    /// The model answering, which the persona may pick.
+    fn model(&self) -> &str;
+
+    /// Has another model answer from now on.
+    fn set_model(&mut self, model: String);
+
+    /// The conversation so far, starting with the system prompt.
+    fn history(&self) -> &[Message];
+
+    /// Forgets everything said so far.
+    fn clear(&mut self);
+
+    /// Forgets the last turn, handing back what was said in it.
+    fn undo(&mut self) -> Option<String>;
+
+    /// Asks for more of the last answer, which was cut off, adding it to the same turn.
+    fn continue_answer(&mut self, on_delta: &mut dyn FnMut(&str)) -> Result<AIResponse, SkyError>;
+
+    /// The tokens spent so far, and what they cost.
+    fn spending(&self) -> &Spending;
+
+    /// Saves the conversation as the session called `name`, or the one it was resumed from,
+    /// and keeps saving it there after every turn. Gives back the name it was saved under.
+    fn save(&mut self, name: Option<&str>) -> Result<String, SkyError>;
 }
 
-#[derive(Debug)]
 pub struct ChatWithAI {
-    c: Vec<String>,
+    c: Vec<Message>,
     secrets: Config,
+    agent: ureq::Agent,
+    provider: Box<dyn Provider>,
+    persona: Persona,
+    on_retry: Box<dyn Fn(Duration)>,
+    /// Told about trouble that doesn't stop the chat, like a session that couldn't be saved.
+    on_warning: Box<dyn Fn(&str)>,
+    toolbox: Toolbox,
+    on_tool: Permit,
+    session: Option<Session>,
+    /// Where the requests and responses are recorded, or played back from.
+    cassette: Option<Cassette>,
+    spending: Spending,
+    /// The answers to the last question, when it was asked for several.
+    alternatives: Vec<String>,
 }
 
 impl Chat for ChatWithAI {
-    fn say(&mut self, text: String) -> AIResponse {
-        self.c.push(text.to_string());
-
-        let req = ureq::json!({
-          "model": "text-davinci-003",
-          "prompt": self.as_prompt(),
-          "temperature": 0.9,
-          "max_tokens": 150,
-          "top_p": 1,
-          "frequency_penalty": 0.0,
-          "presence_penalty": 0.6,
-          "stop": ["You:", "Sky:"]
-        });
+    fn say(&mut self, text: String) -> Result<AIResponse, SkyError> {
+        self.say_choices(text, 1)
+    }
 
-        let res: AIResponse = ureq::post("https://api.openai.com/v1/completions")
-            .set(
-                "Authorization",
-                &format!(
-                    "Bearer {}",
-                    self.secrets.api_key.clone().expect(
-                        "api key should be confirmed to exist before calling this function"
-                    )
-                ),
-            )
-            .send_json(req)
-            .expect("ureq works")
-            .into_json()
-            .expect("ureq works");
-
-        self.c.push(res.to_string());
+    fn say_choices(&mut self, text: String, n: u32) -> Result<AIResponse, SkyError> {
+        usage::check_budget(&self.secrets)?;
+        self.c.push(Message::user(text));
+        self.fit_context();
 
-        res
+        let res = self.complete_choices(&self.c, n);
+        if let Ok(res) = &res {
+            self.alternatives = res.choices().map(|c| c.text().to_string()).collect();
+        }
+
+        let res = self.settle(res)?;
+        match n {
+            1 => {
+                let res = self.use_tools(res, &mut |_| {})?;
+                self.keep_going(res, &mut |_| {})
+            }
+            _ => Ok(res),
+        }
+    }
+
+    fn choose(&mut self, i: usize) -> Result<(), SkyError> {
+        let answer = self
+            .alternatives
+            .get(i)
+            .cloned()
+            .ok_or_else(|| SkyError::NoSuchChoice(i.saturating_add(1)))?;
+        match self.c.last_mut() {
+            Some(last) if last.role == Role::Assistant => last.content = answer,
+            _ => return Err(SkyError::NoSuchChoice(i.saturating_add(1))),
+        }
+        self.save_session();
+        Ok(())
+    }
+
+    fn say_streaming(
+        &mut self,
+        text: String,
+        on_delta: &mut dyn FnMut(&str),
+    ) -> Result<AIResponse, SkyError> {
+        usage::check_budget(&self.secrets)?;
+        self.c.push(Message::user(text));
+        self.fit_context();
+        self.alternatives.clear();
+
+        let res = self.stream(&self.c, on_delta, true);
+        let res = self.settle(res)?;
+        let res = self.use_tools(res, on_delta)?;
+        self.keep_going(res, on_delta)
+    }
+
+    fn continue_answer(&mut self, on_delta: &mut dyn FnMut(&str)) -> Result<AIResponse, SkyError> {
+        usage::check_budget(&self.secrets)?;
+        if self.c.last().map(|m| m.role) != Some(Role::Assistant) {
+            return Err(SkyError::NothingToContinue);
+        }
+
+        let mut prompt = self.c.clone();
+        prompt.push(Message::user(
+            "Continue exactly where you stopped, without repeating anything you already wrote.",
+        ));
+        let res = self.stream(&prompt, on_delta, false)?;
+        if res.choices.is_empty() {
+            return Err(SkyError::EmptyChoices);
+        }
+
+        let usage = res.usage.unwrap_or_else(|| estimate(&prompt, &res));
+        self.account(usage);
+        self.spending.last += usage;
+
+        if let Some(last) = self.c.last_mut() {
+            let more = res.choices[0].message.content.trim_end();
+            // Without a space at the seam, the last word and the next would run together.
+            let joined = last.content.ends_with(char::is_whitespace)
+                || more.starts_with(char::is_whitespace);
+            if !joined && !last.content.is_empty() && !more.is_empty() {
+                last.content.push(' ');
+            }
+            last.content.push_str(more);
+        }
+        self.alternatives.clear();
+        self.save_session();
+
+        Ok(res)
+    }
+
+    fn persona(&self) -> &Persona {
+        &self.persona
+    }
+
+    fn set_persona(&mut self, persona: Persona) {
+        self.c[0] = Message::system(&persona.system_prompt);
+        self.persona = persona;
+        self.alternatives.clear();
+        self.save_session();
+    }
+
+    fn model(&self) -> &str {
+        self.persona.model.as_deref().unwrap_or(&self.secrets.model)
+    }
+
+    fn set_model(&mut self, model: String) {
+        self.secrets.model = model;
+        self.persona.model = None;
+    }
+
+    fn history(&self) -> &[Message] {
+        &self.c
+    }
+
+    fn clear(&mut self) {
+        self.c.truncate(1);
+        self.alternatives.clear();
+        self.save_session();
+    }
+
+    fn undo(&mut self) -> Option<String> {
+        let last = *self.turns().last()?;
+        let question = self.c.drain(last..).next().map(|m| m.content);
+        // They were answers to the question just forgotten.
+        self.alternatives.clear();
+        self.save_session();
+        question
+    }
+
+    fn spending(&self) -> &Spending {
+        &self.spending
+    }
+
+    fn save(&mut self, name: Option<&str>) -> Result<String, SkyError> {
+        let mut session = match (name, self.session.take()) {
+            (Some(name), _) => Session::new(name)?,
+            (None, Some(session)) => session,
+            (None, None) => return Err(SkyError::BadSessionName(String::new())),
+        };
+        session.messages = self.c.clone();
+        session.persona = Some(self.persona.name.clone());
+        session.save()?;
+
+        let name = session.name.clone();
+        self.session = Some(session);
+        Ok(name)
     }
 }
 
 impl ChatWithAI {
-    pub fn new(secrets: Config) -> Self {
-        Self { c: vec![], secrets }
+    pub fn new(secrets: Config) -> Result<Self, SkyError> {
+        let persona = secrets.default_persona();
+
+        Ok(Self {
+            c: vec![Message::system(&persona.system_prompt)],
+            agent: http::agent(&secrets)?,
+            provider: provider::from_config(&secrets),
+            secrets,
+            persona,
+            on_retry: Box::new(|_| {}),
+            on_warning: Box::new(|warning| eprintln!("{warning}")),
+            toolbox: Toolbox::builtin(),
+            on_tool: Box::new(|_, side_effects| !side_effects),
+            session: None,
+            cassette: None,
+            spending: Spending::default(),
+            alternatives: vec![],
+        })
+    }
+
+    /// Continues the conversation in `session`, saving it back after every turn.
+    pub fn resume(secrets: Config, session: Session) -> Result<Self, SkyError> {
+        let mut chat = Self::new(secrets)?;
+        if !session.messages.is_empty() {
+            chat.c = session.messages.clone();
+            chat.c[0] = Message::system(&chat.persona.system_prompt);
+        }
+        chat.session = Some(session);
+        Ok(chat)
+    }
+
+    /// Have `notify` called with the wait whenever a request is about to be retried.
+    pub fn on_retry(mut self, notify: impl Fn(Duration) + 'static) -> Self {
+        self.on_retry = Box::new(notify);
+        self
+    }
+
+    /// Have `notify` told about trouble that doesn't stop the chat, in place of printing it
+    /// to stderr.
+    pub fn on_warning(mut self, notify: impl Fn(&str) + 'static) -> Self {
+        self.on_warning = Box::new(notify);
+        self
+    }
+
+    /// Have `decide` say whether a call to a tool may run, given whether the tool has side
+    /// effects. Only those without side effects run otherwise.
+    pub fn on_tool(mut self, decide: impl Fn(&ToolCall, bool) -> bool + 'static) -> Self {
+        self.on_tool = Box::new(decide);
+        self
+    }
+
+    /// Record every request and response in `cassette`, or play the responses back from it.
+    pub fn cassette(mut self, cassette: Cassette) -> Self {
+        self.cassette = Some(cassette);
+        self
+    }
+
+    /// Have the transcript written out as `report` after every turn, starting it afresh.
+    pub fn reporting(self, report: Report) -> Result<ReportingToFile, SkyError> {
+        let path = report.file()?;
+        std::fs::write(&path, "")?;
+        Ok(ReportingToFile::new(self, path, report.format))
+    }
+
+    /// Sends `messages` off, giving back the body of the response.
+    #[allow(clippy::result_large_err)]
+    fn send(
+        &self,
+        messages: &[Message],
+        n: u32,
+        stream: bool,
+        may_call_tools: bool,
+    ) -> Result<Box<dyn Read>, SkyError> {
+        let cfg = &self.secrets;
+        // The tools go along even when they may not be called, for the calls already made.
+        let tools = match cfg.tools.enabled {
+            true => self.toolbox.definitions(),
+            false => vec![],
+        };
+        let req = self.provider.http_request(&provider::Request {
+            model: self.model(),
+            messages,
+            temperature: self.persona.temperature.unwrap_or(cfg.temperature),
+            max_tokens: cfg.max_tokens,
+            top_p: cfg.top_p,
+            frequency_penalty: cfg.frequency_penalty,
+            presence_penalty: cfg.presence_penalty,
+            n,
+            stream,
+            tools: &tools,
+            may_call_tools: may_call_tools && n == 1,
+        })?;
+
+        let send = || -> Result<Exchange, SkyError> {
+            if let Some(body) = self.provider.answer(&req) {
+                return Ok(Ok(Box::new(Cursor::new(body?))));
+            }
+
+            let res = retry::with_backoff(&cfg.retry, &self.on_retry, || {
+                req.headers
+                    .iter()
+                    .fold(self.agent.post(&req.url), |r, (name, value)| {
+                        r.set(name, value)
+                    })
+                    .send_json(req.body.clone())
+            });
+            match res {
+                Ok(res) => Ok(Ok(res.into_reader())),
+                Err(ureq::Error::Status(code, res)) => {
+                    Ok(Err((code, res.into_string().unwrap_or_default())))
+                }
+                Err(e) => Err(e.into()),
+            }
+        };
+
+        match &self.cassette {
+            Some(cassette) if cassette.replaying() => cassette.play(),
+            Some(cassette) => cassette.record(&req, send),
+            None => send()?.map_err(|(code, body)| SkyError::status(code, &body)),
+        }
+    }
+
+    /// Asks for an answer to `messages`, handing every piece of it to `on_delta` as it arrives.
+    fn stream(
+        &self,
+        messages: &[Message],
+        on_delta: &mut dyn FnMut(&str),
+        may_call_tools: bool,
+    ) -> Result<AIResponse, SkyError> {
+        let res = self.send(messages, 1, true, may_call_tools)?;
+        let reader = BufReader::new(res);
+        let mut answer = None::<String>;
+        let mut calls = BTreeMap::<usize, ToolCall>::new();
+        let mut finish_reason = None;
+        let mut usage = None::<Usage>;
+
+        'lines: for line in reader.lines() {
+            let line = line?;
+            let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+                continue;
+            };
+
+            for event in self.provider.event(data)? {
+                match event {
+                    Event::Delta(delta) => {
+                        on_delta(&delta);
+                        answer.get_or_insert_with(String::new).push_str(&delta);
+                    }
+                    Event::Usage(spent) => {
+                        answer.get_or_insert_with(String::new);
+                        usage.get_or_insert_with(Usage::default).merge(spent);
+                    }
+                    Event::ToolCall {
+                        index,
+                        id,
+                        name,
+                        arguments,
+                    } => {
+                        answer.get_or_insert_with(String::new);
+                        let call = calls.entry(index).or_default();
+                        call.id.extend(id);
+                        call.function.name.extend(name);
+                        call.function.arguments.push_str(&arguments);
+                    }
+                    Event::Finish(reason) => finish_reason = Some(reason),
+                    Event::Done => break 'lines,
+                }
+            }
+        }
+
+        Ok(AIResponse {
+            choices: answer
+                .map(|answer| Choice {
+                    message: Message {
+                        tool_calls: calls.into_values().collect(),
+                        ..Message::assistant(answer)
+                    },
+                    finish_reason,
+                })
+                .into_iter()
+                .collect(),
+            usage,
+        })
+    }
+
+    /// Continues a cut off answer up to [`Config::auto_continue`] times, giving back
+    /// the whole of it.
+    fn keep_going(
+        &mut self,
+        mut res: AIResponse,
+        on_delta: &mut dyn FnMut(&str),
+    ) -> Result<AIResponse, SkyError> {
+        for _ in 0..self.secrets.auto_continue {
+            if !res.truncated() {
+                break;
+            }
+
+            let more = self.continue_answer(on_delta)?;
+            res.choices[0] = Choice {
+                message: self.c[self.c.len() - 1].clone(),
+                finish_reason: more.choices[0].finish_reason.clone(),
+            };
+            res.usage = Some(self.spending.last);
+        }
+
+        Ok(res)
+    }
+
+    /// Runs the tools the answer calls for and asks again with what they gave back, until the
+    /// model answers without calling any or runs out of [`config::Tools::max_rounds`].
+    fn use_tools(
+        &mut self,
+        mut res: AIResponse,
+        on_delta: &mut dyn FnMut(&str),
+    ) -> Result<AIResponse, SkyError> {
+        let rounds = self.secrets.tools.max_rounds;
+        let mut spent = self.spending.last;
+
+        for round in 1..=rounds {
+            let calls = match self.c.last() {
+                Some(m) if m.role == Role::Assistant => m.tool_calls.clone(),
+                _ => vec![],
+            };
+            if calls.is_empty() {
+                break;
+            }
+
+            for call in &calls {
+                let side_effects = self.toolbox.side_effects(&call.function.name);
+                let output = match (self.on_tool)(call, side_effects) {
+                    true => self.toolbox.call(&self.secrets.tools, call),
+                    false => "error: the user didn't allow this to run".to_string(),
+                };
+                self.c.push(Message::tool(&call.id, output));
+            }
+
+            // The last round has to end in an answer.
+            let next = usage::check_budget(&self.secrets)
+                .and_then(|_| self.stream(&self.c, on_delta, round < rounds));
+            res = self.settle(next)?;
+            spent += self.spending.last;
+            self.spending.last = spent;
+        }
+
+        // Calls that never got to run would leave the conversation unanswerable.
+        if let Some(last) = self.c.last_mut() {
+            last.tool_calls.clear();
+        }
+        res.usage = Some(self.spending.last);
+
+        Ok(res)
     }
 
-    fn dialogue(&self) -> String {
+    fn complete(
+        &self,
+        messages: &[Message],
+        n: u32,
+        may_call_tools: bool,
+    ) -> Result<AIResponse, SkyError> {
+        let mut body = String::new();
+        self.send(messages, n, false, may_call_tools)?
+            .read_to_string(&mut body)?;
+        self.provider.response(&body)
+    }
+
+    /// Asks for `n` answers, making up with more requests for APIs that give fewer at a time.
+    fn complete_choices(&self, messages: &[Message], n: u32) -> Result<AIResponse, SkyError> {
+        let mut res = self.complete(messages, n, n == 1)?;
+
+        while (res.choices.len() as u32) < n {
+            let more = self.complete(messages, n - res.choices.len() as u32, n == 1)?;
+            if more.choices.is_empty() {
+                break;
+            }
+            res.choices.extend(more.choices);
+            res.usage = res.usage.zip(more.usage).map(|(mut usage, more)| {
+                usage += more;
+                usage
+            });
+        }
+        res.choices.truncate(n as usize);
+
+        Ok(res)
+    }
+
+    /// Shrinks the history according to the configured [`ContextStrategy`] until the prompt
+    /// fits in [`config::Context::max_prompt_tokens`], or only the question is left.
+    fn fit_context(&mut self) {
+        let context = self.secrets.context.clone();
+
+        if context.strategy == ContextStrategy::SlidingWindow {
+            while self.turns().len() > context.window_turns + 1 {
+                self.drop_oldest_turn();
+            }
+        }
+
+        while context::count_tokens(&self.c) > context.max_prompt_tokens {
+            let shrunk = match context.strategy {
+                ContextStrategy::Summarize => self.summarize_oldest_turns(),
+                _ => self.drop_oldest_turn(),
+            };
+
+            if !shrunk {
+                break;
+            }
+        }
+    }
+
+    /// Where each turn starts in the history, the last one being the question being asked.
+    fn turns(&self) -> Vec<usize> {
         self.c
-            .chunks(2)
-            .filter_map(|chunk| match chunk {
-                [h, a] => Some(format!("You: {h}\nSky: {a}\n")),
-                [h] => Some(format!("You: {h}\nSky:")),
-                _ => None,
-            })
+            .iter()
+            .enumerate()
+            .filter(|(_, m)| m.role == Role::User)
+            .map(|(i, _)| i)
             .collect()
     }
 
-    fn as_prompt(&self) -> String {
-        const PRELUDE: &str = "The following is a conversation between you and an AI assistant named Sky. Sky is helpful, creative, clever, and very friendly.";
-        let dialogue = self.dialogue();
+    fn drop_oldest_turn(&mut self) -> bool {
+        match self.turns()[..] {
+            [first, second, ..] => {
+                self.c.drain(first..second);
+                true
+            }
+            _ => false,
+        }
+    }
 
-        format!("{PRELUDE}\n{dialogue}")
+    /// Replaces the older half of the past turns, along with any earlier summary, with a
+    /// summary the model writes of them, falling back to dropping a turn if that fails.
+    fn summarize_oldest_turns(&mut self) -> bool {
+        let turns = self.turns();
+        if turns.len() < 2 {
+            return false;
+        }
+        let end = turns[((turns.len() - 1) / 2).max(1)];
+
+        let mut prompt = self.c[..end].to_vec();
+        prompt.push(Message::user(
+            "Summarize our conversation so far in a few sentences, keeping any details that may matter later.",
+        ));
+
+        let res = self.complete(&prompt, 1, false);
+        if let Ok(summary) = &res {
+            let usage = summary.usage.unwrap_or_else(|| estimate(&prompt, summary));
+            self.account(usage);
+        }
+
+        match res {
+            Ok(summary) if !summary.choices.is_empty() => {
+                self.c.splice(
+                    1..end,
+                    [Message::system(format!(
+                        "Summary of the earlier conversation: {summary}"
+                    ))],
+                );
+                true
+            }
+            _ => self.drop_oldest_turn(),
+        }
     }
+
+    /// Records the answer in the conversation, or forgets the turn if there is none, so
+    /// that a failed turn can simply be tried again.
+    fn settle(&mut self, res: Result<AIResponse, SkyError>) -> Result<AIResponse, SkyError> {
+        let res = res.and_then(|res| match res.choices.is_empty() {
+            true => Err(SkyError::EmptyChoices),
+            false => Ok(res),
+        });
+
+        match &res {
+            Ok(res) => {
+                let usage = res.usage.unwrap_or_else(|| estimate(&self.c, res));
+                self.account(usage);
+                self.spending.last = usage;
+
+                self.c.push(Message {
+                    content: res.to_string(),
+                    ..res.choices[0].message.clone()
+                });
+                self.save_session();
+            }
+            Err(_) => {
+                if let Some(&start) = self.turns().last() {
+                    self.c.truncate(start);
+                }
+            }
+        }
+
+        res
+    }
+
+    /// Counts what a request spent towards the conversation and today's spending.
+    fn account(&mut self, usage: Usage) {
+        let model = self.model().to_string();
+        let cost = self.spending.add(&self.secrets, &model, usage);
+        if let Err(e) = usage::spend(cost) {
+            (self.on_warning)(&e.to_string());
+        }
+    }
+
+    fn save_session(&mut self) {
+        if let Some(session) = &mut self.session {
+            session.messages = self.c.clone();
+            session.persona = Some(self.persona.name.clone());
+            if let Err(e) = session.save() {
+                (self.on_warning)(&e.to_string());
+            }
+        }
+    }
+}
+
+/// What answering `prompt` with `res` likely took, for when the API doesn't say.
+fn estimate(prompt: &[Message], res: &AIResponse) -> Usage {
+    Usage::new(
+        context::count_tokens(prompt) as u32,
+        res.choices()
+            .map(|c| context::count_text(c.text()))
+            .sum::<usize>() as u32,
+    )
+}
+
+fn dialogue(messages: &[Message], assistant: &str) -> String {
+    messages
+        .iter()
+        .filter_map(|m| match m.role {
+            Role::User => Some(format!("You: {}\n", m.content)),
+            Role::Assistant if m.content.trim().is_empty() => None,
+            Role::Assistant => Some(format!("{assistant}: {}\n", m.content)),
+            Role::System | Role::Tool => None,
+        })
+        .collect()
 }
 
 impl Display for ChatWithAI {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.dialogue())
+        write!(f, "{}", dialogue(&self.c, &self.persona.name))
     }
 }
 
 pub struct ReportingToFile {
     chat: ChatWithAI,
-    file: File,
+    path: PathBuf,
+    format: ReportFormat,
+    transcript: Transcript,
 }
 
 impl ReportingToFile {
-    pub fn new(chat: ChatWithAI, file: File) -> Self {
-        Self { chat, file }
+    pub fn new(chat: ChatWithAI, path: PathBuf, format: ReportFormat) -> Self {
+        let transcript = Transcript::new(&chat.secrets);
+        Self {
+            chat,
+            path,
+            format,
+            transcript,
+        }
+    }
+}
+
+impl ReportingToFile {
+    /// Adds the turn to the transcript and writes the whole of it out again.
+    fn record(&mut self, question: String, asked: u64) {
+        let answer = self
+            .chat
+            .c
+            .last()
+            .map(|m| m.content.clone())
+            .unwrap_or_default();
+        let spending = self.chat.spending.clone();
+
+        self.transcript.messages.extend([
+            Entry {
+                role: Role::User,
+                content: question,
+                time: asked,
+                persona: None,
+                model: None,
+                usage: None,
+            },
+            Entry {
+                role: Role::Assistant,
+                content: answer,
+                time: session::now(),
+                persona: Some(self.chat.persona.name.clone()),
+                model: Some(self.chat.model().to_string()),
+                usage: Some(spending.last),
+            },
+        ]);
+        self.transcript.usage = spending.total;
+        self.transcript.cost = spending.cost;
+
+        self.write();
+    }
+
+    fn write(&mut self) {
+        let written = self
+            .transcript
+            .render(self.format)
+            .and_then(|report| Ok(std::fs::write(&self.path, report)?));
+        if let Err(e) = written {
+            (self.chat.on_warning)(&e.to_string());
+        }
     }
 }
 
 impl Chat for ReportingToFile {
-    fn say(&mut self, text: String) -> AIResponse {
-        self.file
-            .write_fmt(format_args!("\nYou: {}\n", text))
-            .map_err(|e| eprintln!("{e}"))
-            .ok();
-        let res = self.chat.say(text);
-        self.file
-            .write_fmt(format_args!("\nSky: {}\n", res.to_string()))
-            .map_err(|e| eprintln!("{e}"))
-            .ok();
-
-        self.file.flush().map_err(|e| eprintln!("{e}")).ok();
-        res
+    fn say(&mut self, text: String) -> Result<AIResponse, SkyError> {
+        let asked = session::now();
+        let res = self.chat.say(text.clone())?;
+        self.record(text, asked);
+        Ok(res)
+    }
+
+    fn say_choices(&mut self, text: String, n: u32) -> Result<AIResponse, SkyError> {
+        let asked = session::now();
+        let res = self.chat.say_choices(text.clone(), n)?;
+        self.record(text, asked);
+        Ok(res)
+    }
+
+    fn continue_answer(&mut self, on_delta: &mut dyn FnMut(&str)) -> Result<AIResponse, SkyError> {
+        let res = self.chat.continue_answer(on_delta)?;
+        if let (Some(entry), Some(answer)) =
+            (self.transcript.messages.last_mut(), self.chat.c.last())
+        {
+            entry.content = answer.content.clone();
+            entry.usage = Some(self.chat.spending.last);
+        }
+        self.transcript.usage = self.chat.spending.total;
+        self.transcript.cost = self.chat.spending.cost;
+        self.write();
+        Ok(res)
+    }
+
+    fn choose(&mut self, i: usize) -> Result<(), SkyError> {
+        self.chat.choose(i)?;
+        if let (Some(entry), Some(answer)) =
+            (self.transcript.messages.last_mut(), self.chat.c.last())
+        {
+            entry.content = answer.content.clone();
+        }
+        self.write();
+        Ok(())
+    }
+
+    fn say_streaming(
+        &mut self,
+        text: String,
+        on_delta: &mut dyn FnMut(&str),
+    ) -> Result<AIResponse, SkyError> {
+        let asked = session::now();
+        let res = self.chat.say_streaming(text.clone(), on_delta)?;
+        self.record(text, asked);
+        Ok(res)
+    }
+
+    fn persona(&self) -> &Persona {
+        self.chat.persona()
+    }
+
+    fn set_persona(&mut self, persona: Persona) {
+        self.chat.set_persona(persona)
+    }
+
+    fn model(&self) -> &str {
+        self.chat.model()
+    }
+
+    fn set_model(&mut self, model: String) {
+        self.chat.set_model(model)
+    }
+
+    fn history(&self) -> &[Message] {
+        self.chat.history()
+    }
+
+    fn clear(&mut self) {
+        self.chat.clear()
+    }
+
+    fn undo(&mut self) -> Option<String> {
+        self.chat.undo()
+    }
+
+    fn spending(&self) -> &Spending {
+        self.chat.spending()
+    }
+
+    fn save(&mut self, name: Option<&str>) -> Result<String, SkyError> {
+        self.chat.save(name)
     }
 }
 
+/// Starts a chat from `cfg`, or resumes `session`, going through `cassette` if there is one.
+/// The chat is left for the caller to set up further, e.g. with [`ChatWithAI::on_retry`].
 #[inline]
-pub fn chat_factory(cfg: Config, report: bool) -> io::Result<Box<dyn Chat>> {
-    match cfg.api_key {
-        Some(_) => {
-            if report {
-                let now = UNIX_EPOCH
-                    .elapsed()
-                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
-                    .as_millis();
-                let file = File::create(format!("./chat-with-sky-{now}"))?;
-                return Ok(Box::new(ReportingToFile::new(ChatWithAI::new(cfg), file)));
-            } else {
-                return Ok(Box::new(ChatWithAI::new(cfg)));
-            }
-        }
-        None => panic!("need an api key"),
+pub fn chat_factory(
+    cfg: Config,
+    session: Option<Session>,
+    cassette: Option<Cassette>,
+) -> Result<ChatWithAI, SkyError> {
+    let replaying = cassette.as_ref().is_some_and(Cassette::replaying);
+    if cfg.provider.needs_api_key() && cfg.api_key.is_none() && !replaying {
+        return Err(SkyError::MissingApiKey);
     }
+
+    let chat = match session {
+        Some(session) => ChatWithAI::resume(cfg, session)?,
+        None => ChatWithAI::new(cfg)?,
+    };
+    Ok(match cassette {
+        Some(cassette) => chat.cassette(cassette),
+        None => chat,
+    })
 }
@@ -1,58 +1,418 @@
-use clap::{Parser, Subcommand};
-use sky::{config::Config, *};
+use clap::{error::ErrorKind, Args, CommandFactory, Parser, Subcommand};
+use sky::{
+    cassette::Cassette,
+    config::{self, Config},
+    error::SkyError,
+    report::{Report, ReportFormat},
+    screen::Notices,
+    session::Session,
+    tools::{self, ToolCall},
+    *,
+};
 use std::{
     error::Error,
-    io::{stdin, stdout, Write},
+    io::{stdin, stdout, IsTerminal, Read, Write},
+    path::PathBuf,
+    time::Duration,
 };
 
 /// An AI chat assistant powered by Openai.
 #[derive(Parser)]
 #[command(author, version, about)]
 struct Cli {
-    /// Should Sky print the conversation to stderr.
-    #[arg(short)]
-    print: bool,
+    /// Ask a single question made of everything read from stdin, print the answer and exit.
+    #[arg(long)]
+    stdin: bool,
+
+    /// Chat in a full-screen interface instead of line by line.
+    #[arg(long, conflicts_with = "stdin")]
+    tui: bool,
+
+    #[command(flatten)]
+    chat: ChatArgs,
 
     #[command(subcommand)]
     command: Option<Command>,
 }
 
+#[derive(Args)]
+struct ChatArgs {
+    /// Should Sky write the conversation to a file.
+    #[arg(short)]
+    print: bool,
+
+    /// How to write the conversation, implies -p.
+    #[arg(long, value_enum)]
+    report_format: Option<ReportFormat>,
+
+    /// File to write the conversation to, or directory to write it in, implies -p.
+    #[arg(long)]
+    report_path: Option<PathBuf>,
+
+    /// Name of a session to resume, or start if it doesn't exist yet.
+    #[arg(short, long)]
+    session: Option<String>,
+
+    /// Name of the persona to chat with.
+    #[arg(long)]
+    persona: Option<String>,
+
+    /// Name of the profile to use, in place of the default one.
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// Let the model read files, list directories, grep and run the allowed commands.
+    #[arg(long)]
+    tools: bool,
+
+    /// Directory to record every request and the raw response to it in.
+    #[arg(long, value_name = "DIR", conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Directory of recorded responses to play back, in place of asking the API.
+    #[arg(long, value_name = "DIR")]
+    replay: Option<PathBuf>,
+
+    #[command(flatten)]
+    params: Params,
+}
+
+impl ChatArgs {
+    /// Sets up the chat, asking on the terminal before a tool with side effects runs and
+    /// printing notices about retries and trouble to stderr. With a `screen` to send the
+    /// notices to instead, as when it is in full screen, such tools never run.
+    fn open(self, screen: Option<Notices>) -> Result<(Config, Box<dyn Chat>), Box<dyn Error>> {
+        let mut cfg: Config = confy::load("sky", None)?;
+        cfg.use_profile(self.profile.as_deref())?;
+        cfg.resolve_api_key()?;
+        self.params.apply(&mut cfg);
+        cfg.tools.enabled |= self.tools;
+
+        let session = self
+            .session
+            .as_deref()
+            .map(Session::load_or_new)
+            .transpose()?;
+
+        match (
+            self.persona,
+            session.as_ref().and_then(|s| s.persona.clone()),
+        ) {
+            (Some(name), _) => cfg.persona = Some(name),
+            (None, Some(name)) if cfg.find_persona(&name).is_ok() => cfg.persona = Some(name),
+            _ => {}
+        }
+        cfg.validate()?;
+
+        let report = (self.print || self.report_format.is_some() || self.report_path.is_some())
+            .then(|| Report {
+                format: self.report_format.unwrap_or_default(),
+                path: self.report_path,
+            });
+
+        let confirm = screen.is_none();
+        let on_tool = move |call: &ToolCall, side_effects: bool| match (side_effects, confirm) {
+            (false, _) => {
+                if confirm {
+                    eprintln!("(using {})", call.describe());
+                }
+                true
+            }
+            (true, true) => tools::confirm(&format!("allow {}?", call.describe())),
+            (true, false) => false,
+        };
+        let cassette = match (self.record, self.replay) {
+            (Some(dir), _) => Some(Cassette::recording_into(dir)?),
+            (_, Some(dir)) => Some(Cassette::replaying_from(dir)?),
+            _ => None,
+        };
+        let on_retry: Box<dyn Fn(Duration)> = match screen.clone() {
+            Some(notices) => Box::new(move |delay| {
+                let wait = delay.as_secs_f32().ceil();
+                notices.send(format!("retrying in {wait}s"));
+            }),
+            None => Box::new(|delay| {
+                eprint!("(retrying in {}s) ", delay.as_secs_f32().ceil());
+            }),
+        };
+        let on_warning: Box<dyn Fn(&str)> = match screen {
+            Some(notices) => Box::new(move |warning| {
+                notices.send(warning.to_string());
+            }),
+            None => Box::new(|warning| eprintln!("{warning}")),
+        };
+        let chat = chat_factory(cfg.clone(), session, cassette)?
+            .on_retry(on_retry)
+            .on_warning(on_warning)
+            .on_tool(on_tool);
+        let chat: Box<dyn Chat> = match report {
+            Some(report) => Box::new(chat.reporting(report)?),
+            None => Box::new(chat),
+        };
+
+        Ok((cfg, chat))
+    }
+}
+
+#[derive(Args)]
+struct Params {
+    /// Model to chat with, e.g. gpt-4.
+    #[arg(long)]
+    model: Option<String>,
+
+    /// Sampling temperature between 0 and 2, higher is more random.
+    #[arg(long)]
+    temperature: Option<f32>,
+
+    /// Longest answer, in tokens, the model may give.
+    #[arg(long)]
+    max_tokens: Option<u32>,
+
+    /// Probability mass of tokens considered, between 0 and 1.
+    #[arg(long)]
+    top_p: Option<f32>,
+
+    /// Between -2 and 2, positive values discourage repeating the same lines.
+    #[arg(long, allow_negative_numbers = true)]
+    frequency_penalty: Option<f32>,
+
+    /// Between -2 and 2, positive values encourage moving on to new topics.
+    #[arg(long, allow_negative_numbers = true)]
+    presence_penalty: Option<f32>,
+
+    /// How many answers to ask for at once, to pick one from.
+    #[arg(long)]
+    choices: Option<u32>,
+}
+
+impl Params {
+    /// Overrides the parameters in `cfg` with those given, telling if there were any.
+    fn apply(self, cfg: &mut Config) -> bool {
+        fn set<T>(field: &mut T, value: Option<T>) -> bool {
+            value.map(|value| *field = value).is_some()
+        }
+
+        [
+            set(&mut cfg.model, self.model),
+            set(&mut cfg.temperature, self.temperature),
+            set(&mut cfg.max_tokens, self.max_tokens),
+            set(&mut cfg.top_p, self.top_p),
+            set(&mut cfg.frequency_penalty, self.frequency_penalty),
+            set(&mut cfg.presence_penalty, self.presence_penalty),
+            set(&mut cfg.choices, self.choices),
+        ]
+        .contains(&true)
+    }
+}
+
 #[derive(Subcommand)]
 enum Command {
     /// Set some runtime configuration, most importantly the Openai API KEY
+    #[command(args_conflicts_with_subcommands = true)]
     Config {
+        #[command(subcommand)]
+        command: Option<ConfigCommand>,
+
         /// Openai API KEY
         #[arg(short, long)]
         api_key: Option<String>,
 
+        #[command(flatten)]
+        params: Params,
+
         /// Print all the current configurations.
         #[arg(long)]
         show: bool,
     },
+    /// Ask a single question, print the answer and exit.
+    Ask {
+        /// The question, put before whatever is read from stdin.
+        prompt: Option<String>,
+
+        /// Also read stdin into the question, which happens anyway if there is no prompt.
+        #[arg(long)]
+        stdin: bool,
+
+        /// File, directory or glob to put in the question, can be given more than once.
+        #[arg(short, long = "file")]
+        files: Vec<String>,
+    },
+    /// Suggest a shell command for a task, explain it, and run it if you agree.
+    Cmd {
+        /// What the command should do, like `find large files changed this week`.
+        #[arg(required = true)]
+        task: Vec<String>,
+    },
+    /// Manage saved sessions
+    Sessions {
+        #[command(subcommand)]
+        command: SessionsCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Print one setting, like `model` or `retry.max_attempts`.
+    Get { key: String },
+    /// Change one setting, leaving the rest as they are.
+    Set { key: String, value: String },
+    /// Put one setting back to its default.
+    Unset { key: String },
+    /// Open the config file in $VISUAL or $EDITOR.
+    Edit,
+    /// List the settings there are.
+    Keys,
+    /// List the profiles, marking the default one.
+    Profiles,
+}
+
+#[derive(Subcommand)]
+enum SessionsCommand {
+    /// List the saved sessions, most recently used first.
+    List,
+    /// Print the conversation in a session.
+    Show { name: String },
+    /// Delete a session for good.
+    Delete { name: String },
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("error: {e}");
+        let code = e.downcast_ref::<SkyError>().map_or(1, SkyError::exit_code);
+        std::process::exit(code);
+    }
 }
 
-fn main() -> std::result::Result<(), Box<dyn Error>> {
+fn run() -> std::result::Result<(), Box<dyn Error>> {
     let args = Cli::parse();
     match args.command {
-        Some(Command::Config { api_key, show }) => {
+        Some(Command::Config {
+            command: Some(command),
+            ..
+        }) => configure(command)?,
+        Some(Command::Config {
+            command: None,
+            api_key,
+            params,
+            show,
+        }) => {
+            let mut cfg: Config = confy::load("sky", None)?;
+            let mut changed = params.apply(&mut cfg);
             if api_key.is_some() {
-                confy::store("sky", None, Config { api_key })?
+                cfg.api_key = api_key;
+                changed = true;
+            }
+
+            if changed {
+                cfg.validate()?;
+                confy::store("sky", None, cfg)?
             }
 
             if show {
-                println!("{:?}", confy::load::<Config>("sky", None)?);
+                println!("{:?}", confy::load::<Config>("sky", None)?.masked());
             }
         }
+        Some(Command::Ask {
+            prompt,
+            stdin,
+            files,
+        }) => ask(args.chat, prompt, stdin, &files)?,
+        Some(Command::Cmd { task }) => {
+            // The command prompt and its JSON answers have no place in a saved conversation.
+            if args.chat.session.is_some() {
+                Cli::command()
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        "--session can't be used with cmd",
+                    )
+                    .exit();
+            }
+            let (_, mut chat) = args.chat.open(None)?;
+            let status = cmd::run(chat.as_mut(), &task.join(" "))?;
+            if let Some(code) = status.filter(|s| !s.success()).and_then(|s| s.code()) {
+                std::process::exit(code);
+            }
+        }
+        None if args.stdin => ask(args.chat, None, true, &[])?,
+        None if args.tui => {
+            let notices = Notices::default();
+            let (_, chat) = args.chat.open(Some(notices.clone()))?;
+            screen::run(chat, notices)?;
+        }
+        Some(Command::Sessions { command }) => match command {
+            SessionsCommand::List => {
+                for session in Session::list()? {
+                    let turns = session.messages.iter().filter(|m| m.role == Role::User);
+                    println!("{} ({} turns)", session.name, turns.count());
+                }
+            }
+            SessionsCommand::Show { name } => print!("{}", Session::load(&name)?),
+            SessionsCommand::Delete { name } => Session::delete(&name)?,
+        },
         None => {
-            let cfg: Config = confy::load("sky", None)?;
+            let (cfg, chat) = args.chat.open(None)?;
+            repl::run(chat, cfg, repl::Commands::builtin())?;
+        }
+    }
+
+    Ok(())
+}
+
+fn configure(command: ConfigCommand) -> Result<(), Box<dyn Error>> {
+    let mut cfg: Config = confy::load("sky", None)?;
+
+    match command {
+        ConfigCommand::Get { key } => match cfg.masked().get(&key)? {
+            serde_json::Value::String(value) => println!("{value}"),
+            serde_json::Value::Null => {}
+            value => println!("{}", serde_json::to_string_pretty(&value)?),
+        },
+        ConfigCommand::Set { key, value } => {
+            cfg.set(&key, &value)?;
+            cfg.validate()?;
+            confy::store("sky", None, cfg)?;
+        }
+        ConfigCommand::Unset { key } => {
+            cfg.unset(&key)?;
+            cfg.validate()?;
+            confy::store("sky", None, cfg)?;
+        }
+        ConfigCommand::Edit => {
+            let path = confy::get_configuration_file_path("sky", None)?;
+            let editor = std::env::var("VISUAL")
+                .or_else(|_| std::env::var("EDITOR"))
+                .unwrap_or_else(|_| "vi".to_string());
 
-            let mut chat = chat_factory(cfg, args.print)?;
+            let status = std::process::Command::new("sh")
+                .arg("-c")
+                .arg(format!("{editor} \"$1\""))
+                .arg("sh")
+                .arg(&path)
+                .status()?;
+            if !status.success() {
+                return Err(format!("{editor} exited with {status}").into());
+            }
 
-            prompt();
-            for line in stdin().lines().flatten() {
-                let response = chat.say(line);
-                println!("\nSky: {response}\n");
-                prompt();
+            let cfg: Config = confy::load("sky", None).map_err(|e| {
+                SkyError::InvalidConfig(format!("{} can't be read: {e}", path.display()))
+            })?;
+            cfg.validate()?;
+        }
+        ConfigCommand::Profiles => {
+            for (name, profile) in &cfg.profiles {
+                let default = match cfg.default_profile.as_deref() == Some(name.as_str()) {
+                    true => "*",
+                    false => " ",
+                };
+                let provider = profile.provider.unwrap_or(cfg.provider);
+                let model = profile.model.as_deref().unwrap_or(&cfg.model);
+                println!("{default} {name} ({provider:?}, {model})");
+            }
+        }
+        ConfigCommand::Keys => {
+            for key in config::keys() {
+                println!("{key}");
             }
         }
     }
@@ -60,8 +420,61 @@ fn main() -> std::result::Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-#[inline(always)]
-fn prompt() {
-    print!("You: ");
-    stdout().flush().ok();
+/// Asks `prompt` followed by stdin, when asked for or when there is no prompt, along
+/// with the `files`, printing nothing but the answer.
+fn ask(
+    args: ChatArgs,
+    prompt: Option<String>,
+    read_stdin: bool,
+    files: &[String],
+) -> Result<(), Box<dyn Error>> {
+    let mut paths = vec![];
+    for pattern in files {
+        paths.extend(attach::expand(pattern)?);
+    }
+    let mut question = prompt.unwrap_or_default();
+
+    if read_stdin || question.is_empty() {
+        if stdin().is_terminal() {
+            eprintln!("reading the question from stdin, finish with Ctrl-D");
+        }
+
+        let mut input = String::new();
+        stdin().read_to_string(&mut input)?;
+
+        if !question.is_empty() && !input.trim().is_empty() {
+            question.push_str("\n\n");
+        }
+        question.push_str(input.trim_end());
+    }
+
+    if question.trim().is_empty() {
+        return Err(SkyError::NothingToAsk.into());
+    }
+
+    let (cfg, mut chat) = args.open(None)?;
+    if !paths.is_empty() {
+        let notes;
+        (question, notes) = attach::attach(&paths, &question, cfg.context.attach_max_tokens);
+        for note in notes {
+            eprintln!("{note}");
+        }
+    }
+
+    if cfg.choices > 1 {
+        let res = chat.say_choices(question, cfg.choices)?;
+        print!("{}", repl::numbered(&res));
+        return Ok(());
+    }
+
+    let res = chat.say_streaming(question, &mut |delta| {
+        print!("{delta}");
+        stdout().flush().ok();
+    })?;
+    println!();
+    if res.truncated() {
+        eprintln!("{}", repl::TRUNCATED);
+    }
+
+    Ok(())
 }
@@ -0,0 +1,175 @@
+//! A provider that answers without a network, for trying sky out, demos and tests.
+
+use std::{cell::Cell, fs};
+
+use serde_json::{json, Value};
+
+use crate::{
+    config::{self, MockMode},
+    context,
+    error::SkyError,
+    provider::{Event, HttpRequest, Provider, Request},
+    usage::Usage,
+    AIResponse, Message,
+};
+
+/// Makes up its answers in OpenAI's format and reads them back with `format`.
+pub struct Mock {
+    mode: MockMode,
+    path: String,
+    /// How many answers have been given, which picks the next one from a script or fixtures.
+    served: Cell<usize>,
+    format: Box<dyn Provider>,
+}
+
+impl Mock {
+    pub fn new(cfg: &config::Mock, format: Box<dyn Provider>) -> Self {
+        Self {
+            mode: cfg.mode,
+            path: cfg.path.clone().unwrap_or_default(),
+            served: Cell::new(0),
+            format,
+        }
+    }
+
+    /// The next `n` answers, taking them from the script or repeating the question.
+    fn texts(&self, messages: &[Message], n: usize) -> Result<Vec<String>, SkyError> {
+        let served = self.served.replace(self.served.get() + n);
+
+        match self.mode {
+            MockMode::Echo => {
+                let last = messages.last().map(|m| m.content.clone());
+                Ok(vec![last.unwrap_or_default(); n])
+            }
+            _ => {
+                let script = script(&fs::read_to_string(&self.path)?);
+                match script.get(served..served + n) {
+                    Some(texts) => Ok(texts.to_vec()),
+                    None => Err(SkyError::MockExhausted(self.path.clone())),
+                }
+            }
+        }
+    }
+
+    /// The response recorded in the next fixture, as the API sent it.
+    fn fixture(&self) -> Result<String, SkyError> {
+        let served = self.served.replace(self.served.get() + 1);
+
+        let mut fixtures: Vec<_> = fs::read_dir(&self.path)?
+            .filter_map(Result::ok)
+            .map(|e| e.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "response"))
+            .collect();
+        fixtures.sort();
+
+        match fixtures.get(served) {
+            Some(path) => Ok(fs::read_to_string(path)?),
+            None => Err(SkyError::MockExhausted(self.path.clone())),
+        }
+    }
+}
+
+impl Provider for Mock {
+    fn http_request(&self, req: &Request) -> Result<HttpRequest, SkyError> {
+        self.format.http_request(req)
+    }
+
+    fn response(&self, body: &str) -> Result<AIResponse, SkyError> {
+        self.format.response(body)
+    }
+
+    fn event(&self, data: &str) -> Result<Vec<Event>, SkyError> {
+        self.format.event(data)
+    }
+
+    fn answer(&self, req: &HttpRequest) -> Option<Result<String, SkyError>> {
+        if self.mode == MockMode::Fixtures {
+            return Some(self.fixture());
+        }
+
+        let messages: Vec<Message> =
+            serde_json::from_value(req.body["messages"].clone()).unwrap_or_default();
+        let n = req.body["n"].as_u64().unwrap_or(1) as usize;
+        let max_tokens = req.body["max_tokens"].as_u64().unwrap_or(u64::MAX) as usize;
+
+        let texts = match self.texts(&messages, n) {
+            Ok(texts) => texts,
+            Err(e) => return Some(Err(e)),
+        };
+        let answers: Vec<(String, &str)> = texts.iter().map(|t| cut(t, max_tokens)).collect();
+        let usage = Usage::new(
+            context::count_tokens(&messages) as u32,
+            answers
+                .iter()
+                .map(|(text, _)| context::count_text(text) as u32)
+                .sum(),
+        );
+
+        Some(Ok(match req.body["stream"].as_bool().unwrap_or(false) {
+            true => stream(&answers[0], usage),
+            false => json!({
+                "choices": answers
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (text, finish_reason))| json!({
+                        "index": i,
+                        "message": { "role": "assistant", "content": text },
+                        "finish_reason": finish_reason
+                    }))
+                    .collect::<Vec<_>>(),
+                "usage": usage
+            })
+            .to_string(),
+        }))
+    }
+}
+
+/// The answers in a script, which are separated by lines of `---`.
+fn script(text: &str) -> Vec<String> {
+    let mut answers = vec![String::new()];
+    for line in text.lines() {
+        match line.trim_end() == "---" {
+            true => answers.push(String::new()),
+            false => {
+                if let Some(answer) = answers.last_mut() {
+                    answer.push_str(line);
+                    answer.push('\n');
+                }
+            }
+        }
+    }
+
+    answers.iter().map(|a| a.trim().to_string()).collect()
+}
+
+/// `text` cut down to `max_tokens`, with why the answer ended.
+fn cut(text: &str, max_tokens: usize) -> (String, &'static str) {
+    if context::count_text(text) <= max_tokens {
+        return (text.to_string(), "stop");
+    }
+
+    let mut kept = String::new();
+    for word in text.split_inclusive(' ') {
+        if context::count_text(&format!("{kept}{}", word.trim_end())) > max_tokens {
+            break;
+        }
+        kept.push_str(word);
+    }
+    (kept.trim_end().to_string(), "length")
+}
+
+/// An answer as server-sent events, a word at a time.
+fn stream((text, finish_reason): &(String, &str), usage: Usage) -> String {
+    let event = |data: Value| format!("data: {data}\n\n");
+
+    let mut body: String = text
+        .split_inclusive(' ')
+        .map(|word| event(json!({ "choices": [{ "index": 0, "delta": { "content": word } }] })))
+        .collect();
+    body.push_str(&event(json!({
+        "choices": [{ "index": 0, "delta": {}, "finish_reason": finish_reason }]
+    })));
+    body.push_str(&event(json!({ "choices": [], "usage": usage })));
+    body.push_str("data: [DONE]\n\n");
+    body
+}
@@ -0,0 +1,535 @@
+//! The chat APIs sky can talk to, each translating the conversation into its own format.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    config::Config,
+    error::SkyError,
+    mock::Mock,
+    tools::{Definition, FunctionCall, ToolCall},
+    usage::Usage,
+    AIResponse, Choice, Message, Role,
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProviderKind {
+    #[default]
+    Openai,
+    /// Azure OpenAI, where the model is the name of a deployment.
+    Azure,
+    Anthropic,
+    /// A local server with an OpenAI-compatible API, like Ollama or llama.cpp.
+    Local,
+    /// Answers made up without a network, as set in [`config::Mock`](crate::config::Mock).
+    Mock,
+}
+
+impl ProviderKind {
+    pub fn needs_api_key(&self) -> bool {
+        !matches!(self, ProviderKind::Local | ProviderKind::Mock)
+    }
+
+    /// The environment variable the provider's API key is usually kept in.
+    pub fn api_key_env(&self) -> Option<&'static str> {
+        match self {
+            ProviderKind::Openai => Some("OPENAI_API_KEY"),
+            ProviderKind::Azure => Some("AZURE_OPENAI_API_KEY"),
+            ProviderKind::Anthropic => Some("ANTHROPIC_API_KEY"),
+            ProviderKind::Local | ProviderKind::Mock => None,
+        }
+    }
+
+    fn default_base_url(&self) -> &'static str {
+        match self {
+            ProviderKind::Openai => "https://api.openai.com/v1",
+            ProviderKind::Azure => "",
+            ProviderKind::Anthropic => "https://api.anthropic.com/v1",
+            ProviderKind::Local => "http://localhost:11434/v1",
+            ProviderKind::Mock => "mock://sky",
+        }
+    }
+}
+
+/// Everything that goes into asking for a completion, whichever API it goes to.
+pub struct Request<'a> {
+    pub model: &'a str,
+    pub messages: &'a [Message],
+    pub temperature: f32,
+    pub max_tokens: u32,
+    pub top_p: f32,
+    pub frequency_penalty: f32,
+    pub presence_penalty: f32,
+    /// How many answers to give, which not every API can do at once.
+    pub n: u32,
+    pub stream: bool,
+    /// Tools to tell the model about.
+    pub tools: &'a [Definition],
+    /// Whether the model may call them, rather than only make sense of the calls it made.
+    pub may_call_tools: bool,
+}
+
+/// A request as it goes over the wire.
+pub struct HttpRequest {
+    pub url: String,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: serde_json::Value,
+}
+
+/// Part of what a server-sent event of a streamed answer amounts to.
+pub enum Event {
+    /// More of the answer, possibly empty.
+    Delta(String),
+    /// Some or all of the tokens spent on the answer.
+    Usage(Usage),
+    /// Why the answer ended, in OpenAI's terms like `stop` or `length`.
+    Finish(String),
+    /// Part of a call to a tool, pieced together by `index` as its arguments arrive.
+    ToolCall {
+        index: usize,
+        id: Option<String>,
+        name: Option<String>,
+        arguments: String,
+    },
+    Done,
+}
+
+pub trait Provider {
+    fn http_request(&self, req: &Request) -> Result<HttpRequest, SkyError>;
+
+    /// Reads a whole answer.
+    fn response(&self, body: &str) -> Result<AIResponse, SkyError>;
+
+    /// Reads the data of one server-sent event of a streamed answer, which may carry
+    /// several things at once or, when it is only bookkeeping, nothing at all.
+    fn event(&self, data: &str) -> Result<Vec<Event>, SkyError>;
+
+    /// The body of the response to `req`, for providers that answer it themselves rather
+    /// than send it anywhere.
+    fn answer(&self, _req: &HttpRequest) -> Option<Result<String, SkyError>> {
+        None
+    }
+}
+
+pub fn from_config(cfg: &Config) -> Box<dyn Provider> {
+    let base_url = cfg
+        .base_url
+        .as_deref()
+        .unwrap_or(cfg.provider.default_base_url())
+        .trim_end_matches('/')
+        .to_string();
+    let api_key = cfg.api_key.clone();
+
+    match cfg.provider {
+        ProviderKind::Openai | ProviderKind::Local => Box::new(OpenAi {
+            base_url,
+            api_key,
+            auth: Auth::Bearer,
+            api_version: None,
+            stream_usage: cfg.provider == ProviderKind::Openai,
+        }),
+        ProviderKind::Azure => Box::new(OpenAi {
+            base_url,
+            api_key,
+            auth: Auth::ApiKeyHeader,
+            api_version: Some(cfg.azure_api_version.clone()),
+            stream_usage: false,
+        }),
+        ProviderKind::Anthropic => Box::new(Anthropic { base_url, api_key }),
+        ProviderKind::Mock => Box::new(Mock::new(
+            &cfg.mock,
+            Box::new(OpenAi {
+                base_url,
+                api_key,
+                auth: Auth::Bearer,
+                api_version: None,
+                stream_usage: true,
+            }),
+        )),
+    }
+}
+
+enum Auth {
+    /// `Authorization: Bearer <key>`
+    Bearer,
+    /// `api-key: <key>`
+    ApiKeyHeader,
+}
+
+/// OpenAI's chat completions API, and the servers that copy it.
+struct OpenAi {
+    base_url: String,
+    api_key: Option<String>,
+    auth: Auth,
+    /// Set for Azure, which addresses models by deployment and versions its API.
+    api_version: Option<String>,
+    /// Whether to ask for the tokens spent at the end of a streamed answer,
+    /// which not every server copying the API understands.
+    stream_usage: bool,
+}
+
+#[derive(Deserialize)]
+struct Delta {
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<ToolCallDelta>,
+}
+
+#[derive(Deserialize)]
+struct ToolCallDelta {
+    index: usize,
+    id: Option<String>,
+    #[serde(default)]
+    function: FunctionDelta,
+}
+
+#[derive(Default, Deserialize)]
+struct FunctionDelta {
+    name: Option<String>,
+    arguments: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ChunkChoice {
+    delta: Delta,
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Chunk {
+    choices: Vec<ChunkChoice>,
+    usage: Option<Usage>,
+}
+
+impl Provider for OpenAi {
+    fn http_request(&self, req: &Request) -> Result<HttpRequest, SkyError> {
+        let url = match &self.api_version {
+            Some(version) => format!(
+                "{}/openai/deployments/{}/chat/completions?api-version={version}",
+                self.base_url, req.model
+            ),
+            None => format!("{}/chat/completions", self.base_url),
+        };
+
+        let headers = match (&self.api_key, &self.auth) {
+            (Some(key), Auth::Bearer) => vec![("Authorization", format!("Bearer {key}"))],
+            (Some(key), Auth::ApiKeyHeader) => vec![("api-key", key.clone())],
+            (None, Auth::Bearer) if self.api_version.is_none() => vec![],
+            (None, _) => return Err(SkyError::MissingApiKey),
+        };
+
+        let mut body = json!({
+                "model": req.model,
+                "messages": req.messages,
+                "temperature": req.temperature,
+                "max_tokens": req.max_tokens,
+                "top_p": req.top_p,
+                "frequency_penalty": req.frequency_penalty,
+                "presence_penalty": req.presence_penalty,
+                "stream": req.stream
+        });
+        if req.n > 1 {
+            body["n"] = req.n.into();
+        }
+        if req.stream && self.stream_usage {
+            body["stream_options"] = json!({ "include_usage": true });
+        }
+        if !req.tools.is_empty() {
+            body["tools"] = req
+                .tools
+                .iter()
+                .map(|tool| json!({ "type": "function", "function": tool }))
+                .collect();
+            if !req.may_call_tools {
+                body["tool_choice"] = "none".into();
+            }
+        }
+
+        Ok(HttpRequest { url, headers, body })
+    }
+
+    fn response(&self, body: &str) -> Result<AIResponse, SkyError> {
+        Ok(serde_json::from_str(body)?)
+    }
+
+    fn event(&self, data: &str) -> Result<Vec<Event>, SkyError> {
+        if data == "[DONE]" {
+            return Ok(vec![Event::Done]);
+        }
+
+        let chunk: Chunk = serde_json::from_str(data)?;
+        let mut events = vec![];
+        if let Some(choice) = chunk.choices.into_iter().next() {
+            events.push(Event::Delta(choice.delta.content.unwrap_or_default()));
+            events.extend(
+                choice
+                    .delta
+                    .tool_calls
+                    .into_iter()
+                    .map(|call| Event::ToolCall {
+                        index: call.index,
+                        id: call.id,
+                        name: call.function.name,
+                        arguments: call.function.arguments.unwrap_or_default(),
+                    }),
+            );
+            events.extend(choice.finish_reason.map(Event::Finish));
+        }
+        events.extend(chunk.usage.map(Event::Usage));
+
+        Ok(events)
+    }
+}
+
+/// Anthropic's messages API.
+struct Anthropic {
+    base_url: String,
+    api_key: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ContentBlock {
+    #[serde(rename = "type", default)]
+    kind: String,
+    text: Option<String>,
+    /// The call, in `tool_use` blocks.
+    id: Option<String>,
+    name: Option<String>,
+    input: Option<Value>,
+    /// A piece of the arguments of a call, in `input_json_delta`s.
+    partial_json: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct AnthropicUsage {
+    input_tokens: u32,
+    output_tokens: u32,
+}
+
+impl From<AnthropicUsage> for Usage {
+    fn from(usage: AnthropicUsage) -> Self {
+        Usage::new(usage.input_tokens, usage.output_tokens)
+    }
+}
+
+#[derive(Deserialize)]
+struct AnthropicResponse {
+    content: Vec<ContentBlock>,
+    stop_reason: Option<String>,
+    usage: Option<AnthropicUsage>,
+}
+
+#[derive(Deserialize)]
+struct StopDelta {
+    stop_reason: Option<String>,
+}
+
+/// Anthropic's reason for stopping, in OpenAI's terms.
+fn finish_reason(stop_reason: String) -> String {
+    match stop_reason.as_str() {
+        "max_tokens" => "length".to_string(),
+        "end_turn" | "stop_sequence" => "stop".to_string(),
+        "tool_use" => "tool_calls".to_string(),
+        _ => stop_reason,
+    }
+}
+
+#[derive(Deserialize)]
+struct AnthropicMessage {
+    #[serde(default)]
+    usage: AnthropicUsage,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum AnthropicEvent {
+    MessageStart {
+        message: AnthropicMessage,
+    },
+    ContentBlockStart {
+        index: usize,
+        content_block: ContentBlock,
+    },
+    ContentBlockDelta {
+        #[serde(default)]
+        index: usize,
+        delta: ContentBlock,
+    },
+    /// Carries why the answer ended, and the output tokens counted so far.
+    MessageDelta {
+        delta: StopDelta,
+        #[serde(default)]
+        usage: AnthropicUsage,
+    },
+    MessageStop,
+    #[serde(other)]
+    Other,
+}
+
+impl Provider for Anthropic {
+    fn http_request(&self, req: &Request) -> Result<HttpRequest, SkyError> {
+        let api_key = self.api_key.clone().ok_or(SkyError::MissingApiKey)?;
+
+        // Anthropic takes the system prompt on its own rather than as a message.
+        let system: Vec<&str> = req
+            .messages
+            .iter()
+            .filter(|m| m.role == Role::System)
+            .map(|m| m.content.as_str())
+            .collect();
+
+        let mut body = json!({
+            "model": req.model,
+            "system": system.join("\n\n"),
+            "messages": anthropic_messages(req.messages),
+            "temperature": req.temperature.min(1.0),
+            "max_tokens": req.max_tokens,
+            "stream": req.stream
+        });
+        // Some models refuse both temperature and top_p, so top_p only goes when it matters.
+        if req.top_p < 1.0 {
+            body["top_p"] = req.top_p.into();
+        }
+        if !req.tools.is_empty() {
+            body["tools"] = req
+                .tools
+                .iter()
+                .map(|tool| {
+                    json!({
+                        "name": tool.name,
+                        "description": tool.description,
+                        "input_schema": tool.parameters
+                    })
+                })
+                .collect();
+            if !req.may_call_tools {
+                body["tool_choice"] = json!({ "type": "none" });
+            }
+        }
+
+        Ok(HttpRequest {
+            url: format!("{}/messages", self.base_url),
+            headers: vec![
+                ("x-api-key", api_key),
+                ("anthropic-version", "2023-06-01".to_string()),
+            ],
+            body,
+        })
+    }
+
+    fn response(&self, body: &str) -> Result<AIResponse, SkyError> {
+        let res: AnthropicResponse = serde_json::from_str(body)?;
+        let mut message = Message::assistant("");
+        for block in res.content {
+            match (block.kind.as_str(), block.text) {
+                ("tool_use", _) => message.tool_calls.push(ToolCall {
+                    id: block.id.unwrap_or_default(),
+                    function: FunctionCall {
+                        name: block.name.unwrap_or_default(),
+                        arguments: block.input.unwrap_or_else(|| json!({})).to_string(),
+                    },
+                    ..ToolCall::default()
+                }),
+                (_, Some(text)) => message.content.push_str(&text),
+                _ => {}
+            }
+        }
+
+        Ok(AIResponse {
+            choices: vec![Choice {
+                message,
+                finish_reason: res.stop_reason.map(finish_reason),
+            }],
+            usage: res.usage.map(Usage::from),
+        })
+    }
+
+    fn event(&self, data: &str) -> Result<Vec<Event>, SkyError> {
+        Ok(match serde_json::from_str(data)? {
+            AnthropicEvent::MessageStart { message } => vec![Event::Usage(message.usage.into())],
+            AnthropicEvent::ContentBlockStart {
+                index,
+                content_block,
+            } => match content_block.kind.as_str() {
+                "tool_use" => vec![Event::ToolCall {
+                    index,
+                    id: content_block.id,
+                    name: content_block.name,
+                    arguments: String::new(),
+                }],
+                _ => vec![],
+            },
+            AnthropicEvent::ContentBlockDelta { index, delta } => match delta.partial_json {
+                Some(arguments) => vec![Event::ToolCall {
+                    index,
+                    id: None,
+                    name: None,
+                    arguments,
+                }],
+                None => vec![Event::Delta(delta.text.unwrap_or_default())],
+            },
+            AnthropicEvent::MessageDelta { delta, usage } => delta
+                .stop_reason
+                .map(|reason| Event::Finish(finish_reason(reason)))
+                .into_iter()
+                .chain([Event::Usage(usage.into())])
+                .collect(),
+            AnthropicEvent::MessageStop => vec![Event::Done],
+            AnthropicEvent::Other => vec![],
+        })
+    }
+}
+
+/// The conversation in Anthropic's terms, where calls to tools are `tool_use` blocks of the
+/// assistant's message and what they gave back are `tool_result` blocks of the next user's.
+fn anthropic_messages(messages: &[Message]) -> Vec<Value> {
+    let mut converted: Vec<Value> = vec![];
+    let mut results_open = false;
+
+    for m in messages {
+        match m.role {
+            Role::System => continue,
+            Role::Tool => {
+                let result = json!({
+                    "type": "tool_result",
+                    "tool_use_id": m.tool_call_id,
+                    "content": m.content
+                });
+                match (results_open, converted.last_mut()) {
+                    (true, Some(last)) => {
+                        if let Some(blocks) = last["content"].as_array_mut() {
+                            blocks.push(result);
+                        }
+                    }
+                    _ => converted.push(json!({ "role": "user", "content": [result] })),
+                }
+                results_open = true;
+                continue;
+            }
+            Role::Assistant if !m.tool_calls.is_empty() => {
+                let text = Some(m.content.trim())
+                    .filter(|text| !text.is_empty())
+                    .map(|text| json!({ "type": "text", "text": text }));
+                let calls = m.tool_calls.iter().map(|call| {
+                    json!({
+                        "type": "tool_use",
+                        "id": call.id,
+                        "name": call.function.name,
+                        "input": serde_json::from_str::<Value>(&call.function.arguments)
+                            .unwrap_or_else(|_| json!({}))
+                    })
+                });
+                converted.push(json!({
+                    "role": "assistant",
+                    "content": text.into_iter().chain(calls).collect::<Vec<_>>()
+                }));
+            }
+            role => converted.push(json!({ "role": role, "content": m.content })),
+        }
+        results_open = false;
+    }
+
+    converted
+}
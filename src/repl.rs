@@ -0,0 +1,438 @@
+//! The line by line chat, with multi-line input and slash commands.
+
+use std::{
+    io::{self, stdout, Write},
+    path::PathBuf,
+};
+
+use rustyline::{error::ReadlineError, DefaultEditor};
+
+use crate::{
+    attach,
+    config::{data_dir, Config},
+    error::SkyError,
+    usage, AIResponse, Chat,
+};
+
+/// What the REPL does once a slash command has run.
+pub enum Outcome {
+    Done,
+    /// Send this to the chat, as if it had been typed.
+    Say(String),
+    /// Put these files in the next message.
+    Attach(Vec<PathBuf>),
+    Exit,
+}
+
+/// What a slash command gets to work with.
+pub struct Ctx<'a> {
+    pub chat: &'a mut dyn Chat,
+    pub cfg: &'a Config,
+    pub commands: &'a Commands,
+}
+
+type Handler = Box<dyn Fn(&mut Ctx, &str) -> Result<Outcome, SkyError>>;
+
+struct Command {
+    name: &'static str,
+    usage: &'static str,
+    help: &'static str,
+    handler: Handler,
+}
+
+/// The slash commands the REPL knows, looked up by name before anything reaches the chat.
+#[derive(Default)]
+pub struct Commands {
+    commands: Vec<Command>,
+}
+
+impl Commands {
+    pub fn register(
+        &mut self,
+        name: &'static str,
+        usage: &'static str,
+        help: &'static str,
+        handler: impl Fn(&mut Ctx, &str) -> Result<Outcome, SkyError> + 'static,
+    ) {
+        self.commands.retain(|c| c.name != name);
+        self.commands.push(Command {
+            name,
+            usage,
+            help,
+            handler: Box::new(handler),
+        });
+    }
+
+    /// Runs the slash command `line` starts with, or returns `None` if it isn't one.
+    /// A leading `//` escapes the slash, so the rest is said as is.
+    pub fn dispatch(
+        &self,
+        chat: &mut dyn Chat,
+        cfg: &Config,
+        line: &str,
+    ) -> Option<Result<Outcome, SkyError>> {
+        let command = line.strip_prefix('/')?;
+        if command.starts_with('/') {
+            return Some(Ok(Outcome::Say(command.to_string())));
+        }
+
+        let (name, args) = command
+            .split_once(char::is_whitespace)
+            .unwrap_or((command, ""));
+        let Some(command) = self.commands.iter().find(|c| c.name == name) else {
+            return Some(Err(SkyError::UnknownCommand(name.to_string())));
+        };
+
+        let mut ctx = Ctx {
+            chat,
+            cfg,
+            commands: self,
+        };
+        Some((command.handler)(&mut ctx, args.trim()))
+    }
+
+    pub fn help(&self) -> String {
+        let width = self.commands.iter().map(|c| c.usage.len()).max();
+        let mut help: String = self
+            .commands
+            .iter()
+            .map(|c| {
+                format!(
+                    "  {:width$}  {}\n",
+                    c.usage,
+                    c.help,
+                    width = width.unwrap_or(0)
+                )
+            })
+            .collect();
+        help.push_str(
+            "\nEnd a line with \\ to keep typing on the next one, or wrap a message in ``` lines.\n\
+             Start a message with // to send it with a single leading slash.\n\
+             Mention a file, directory or glob like @src/lib.rs to put it in the message.\n",
+        );
+        help
+    }
+
+    pub fn builtin() -> Self {
+        let mut commands = Self::default();
+
+        commands.register("help", "/help", "list the commands", |ctx, _| {
+            print!("{}", ctx.commands.help());
+            Ok(Outcome::Done)
+        });
+        commands.register("clear", "/clear", "forget the conversation", |ctx, _| {
+            ctx.chat.clear();
+            Ok(Outcome::Done)
+        });
+        commands.register(
+            "save",
+            "/save [name]",
+            "save the conversation as a session, and keep saving it",
+            |ctx, name| {
+                let name = ctx.chat.save(Some(name).filter(|n| !n.is_empty()))?;
+                println!("saved as '{name}'");
+                Ok(Outcome::Done)
+            },
+        );
+        commands.register(
+            "model",
+            "/model [name]",
+            "show or change the model",
+            |ctx, model| {
+                match model {
+                    "" => println!("{}", ctx.chat.model()),
+                    model => ctx.chat.set_model(model.to_string()),
+                }
+                Ok(Outcome::Done)
+            },
+        );
+        commands.register(
+            "persona",
+            "/persona [name]",
+            "list the personas or switch to one",
+            |ctx, name| {
+                if name.is_empty() {
+                    for persona in ctx.cfg.personas() {
+                        let current = match persona.name == ctx.chat.persona().name {
+                            true => "*",
+                            false => " ",
+                        };
+                        println!("{current} {}", persona.name);
+                    }
+                } else {
+                    ctx.chat.set_persona(ctx.cfg.find_persona(name)?);
+                }
+                Ok(Outcome::Done)
+            },
+        );
+        commands.register("undo", "/undo", "forget the last turn", |ctx, _| {
+            match ctx.chat.undo() {
+                Some(question) => println!("forgot: {question}"),
+                None => println!("nothing to undo"),
+            }
+            Ok(Outcome::Done)
+        });
+        commands.register(
+            "retry",
+            "/retry",
+            "ask the last question again",
+            |ctx, _| match ctx.chat.undo() {
+                Some(question) => Ok(Outcome::Say(question)),
+                None => {
+                    println!("nothing to retry");
+                    Ok(Outcome::Done)
+                }
+            },
+        );
+        commands.register(
+            "usage",
+            "/usage",
+            "show the tokens spent and what they cost",
+            |ctx, _| {
+                let spending = ctx.chat.spending();
+                let unpriced = match spending.unpriced {
+                    true => ", not counting models without a price",
+                    false => "",
+                };
+                println!("last answer: {}", spending.last);
+                println!(
+                    "this chat:   {}, about ${:.4}{unpriced}",
+                    spending.total, spending.cost
+                );
+
+                let today = usage::spent_today()?;
+                match ctx.cfg.daily_budget {
+                    Some(budget) => println!("today:       ${today:.4} of ${budget:.2}"),
+                    None => println!("today:       ${today:.4}"),
+                }
+                Ok(Outcome::Done)
+            },
+        );
+        commands.register(
+            "file",
+            "/file <path>...",
+            "put files, directories or globs in the next message",
+            |_, patterns| {
+                if patterns.is_empty() {
+                    println!("usage: /file <path>...");
+                    return Ok(Outcome::Done);
+                }
+
+                let mut files = vec![];
+                for pattern in patterns.split_whitespace() {
+                    files.extend(attach::expand(pattern)?);
+                }
+                Ok(Outcome::Attach(files))
+            },
+        );
+        commands.register(
+            "continue",
+            "/continue",
+            "ask for more of an answer that was cut off",
+            |ctx, _| {
+                print!("\n{}: ", ctx.chat.persona().name);
+                let res = ctx.chat.continue_answer(&mut |delta| {
+                    print!("{delta}");
+                    stdout().flush().ok();
+                })?;
+                if res.truncated() {
+                    print!(" {TRUNCATED}");
+                }
+                println!("\n");
+                Ok(Outcome::Done)
+            },
+        );
+        commands.register(
+            "alternatives",
+            "/alternatives [n]",
+            "ask the last question again for several answers to pick from",
+            |ctx, n| {
+                let n = match n {
+                    "" => ctx.cfg.choices.max(3),
+                    n => n
+                        .parse()
+                        .ok()
+                        .filter(|n| (1..=10).contains(n))
+                        .ok_or_else(|| {
+                            SkyError::InvalidConfig(format!(
+                                "'{n}' isn't a number of answers between 1 and 10"
+                            ))
+                        })?,
+                };
+                let Some(question) = ctx.chat.undo() else {
+                    println!("nothing to ask again");
+                    return Ok(Outcome::Done);
+                };
+
+                let res = ctx.chat.say_choices(question, n)?;
+                print!("\n{}", numbered(&res));
+                println!("(keeping 1, /pick <n> to keep another)\n");
+                Ok(Outcome::Done)
+            },
+        );
+        commands.register(
+            "pick",
+            "/pick <n>",
+            "keep answer n of the last several",
+            |ctx, n| {
+                let n: usize = n.parse().map_err(|_| SkyError::NoSuchChoice(0))?;
+                let i = n.checked_sub(1).ok_or(SkyError::NoSuchChoice(n))?;
+                ctx.chat.choose(i)?;
+                println!("keeping answer {n}");
+                Ok(Outcome::Done)
+            },
+        );
+        commands.register("exit", "/exit", "leave", |_, _| Ok(Outcome::Exit));
+
+        commands
+    }
+}
+
+/// Chats line by line until stdin runs out or the user leaves.
+pub fn run(mut chat: Box<dyn Chat>, cfg: Config, commands: Commands) -> Result<(), SkyError> {
+    let mut editor = DefaultEditor::new().map_err(readline_error)?;
+    let history = data_dir()?.join("history.txt");
+    editor.load_history(&history).ok();
+
+    let mut attached = vec![];
+
+    while let Some(message) = read_message(&mut editor)? {
+        if message.trim().is_empty() {
+            continue;
+        }
+        editor
+            .add_history_entry(message.as_str())
+            .map_err(readline_error)?;
+
+        let message = match commands.dispatch(chat.as_mut(), &cfg, &message) {
+            None => message,
+            Some(Ok(Outcome::Done)) => continue,
+            Some(Ok(Outcome::Say(message))) => message,
+            Some(Ok(Outcome::Attach(files))) => {
+                match files.len() {
+                    1 => println!("1 file will go with the next message"),
+                    n => println!("{n} files will go with the next message"),
+                }
+                attached.extend(files);
+                continue;
+            }
+            Some(Ok(Outcome::Exit)) => break,
+            Some(Err(e)) => {
+                eprintln!("error: {e}");
+                continue;
+            }
+        };
+
+        let (mut message, mentioned) = attach::mentioned(&message);
+        attached.extend(mentioned);
+        if !attached.is_empty() {
+            let notes;
+            (message, notes) = attach::attach(&attached, &message, cfg.context.attach_max_tokens);
+            for note in notes {
+                eprintln!("{note}");
+            }
+            attached.clear();
+        }
+
+        print!("\n{}: ", chat.persona().name);
+        let res = match cfg.choices {
+            1 => chat
+                .say_streaming(message, &mut |delta| {
+                    print!("{delta}");
+                    stdout().flush().ok();
+                })
+                .inspect(|res| {
+                    if res.truncated() {
+                        print!(" {TRUNCATED} /continue for more");
+                    }
+                }),
+            n => chat.say_choices(message, n).inspect(|res| {
+                print!("\n{}", numbered(res));
+                print!("(keeping 1, /pick <n> to keep another)");
+            }),
+        };
+        println!("\n");
+
+        if let Err(e) = res {
+            eprintln!("error: {e}\n");
+        }
+    }
+
+    if let Some(dir) = history.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    editor.save_history(&history).map_err(readline_error)?;
+
+    Ok(())
+}
+
+/// Shown after an answer cut off by `max_tokens`.
+pub const TRUNCATED: &str = "[truncated]";
+
+/// The answers in `res`, numbered from 1 and a blank line apart.
+pub fn numbered(res: &AIResponse) -> String {
+    res.choices()
+        .enumerate()
+        .map(|(i, choice)| match choice.truncated() {
+            true => format!("[{}] {} {TRUNCATED}\n\n", i + 1, choice.text()),
+            false => format!("[{}] {}\n\n", i + 1, choice.text()),
+        })
+        .collect()
+}
+
+/// Reads one message, which carries on over lines ending with `\` or wrapped in ``` fences.
+/// Returns `None` once the input has run out.
+fn read_message(editor: &mut DefaultEditor) -> Result<Option<String>, SkyError> {
+    let mut message = String::new();
+    let mut fenced = false;
+
+    loop {
+        let prompt = match message.is_empty() && !fenced {
+            true => "You: ",
+            false => " ... ",
+        };
+
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                message.clear();
+                fenced = false;
+                continue;
+            }
+            Err(ReadlineError::Eof) if message.is_empty() => return Ok(None),
+            Err(ReadlineError::Eof) => return Ok(Some(message)),
+            Err(e) => return Err(readline_error(e)),
+        };
+
+        if line.trim() == "```" {
+            if fenced {
+                return Ok(Some(message));
+            }
+            fenced = message.is_empty();
+            if fenced {
+                continue;
+            }
+        }
+
+        if !message.is_empty() {
+            message.push('\n');
+        }
+
+        match line.strip_suffix('\\') {
+            Some(line) if !fenced => message.push_str(line),
+            _ => {
+                message.push_str(&line);
+                if !fenced {
+                    return Ok(Some(message));
+                }
+            }
+        }
+    }
+}
+
+fn readline_error(e: ReadlineError) -> SkyError {
+    match e {
+        ReadlineError::Io(e) => SkyError::Io(e),
+        e => SkyError::Io(io::Error::other(e)),
+    }
+}
@@ -0,0 +1,297 @@
+//! Transcripts of a conversation, written out as Markdown, JSON or self-contained HTML.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use serde::Serialize;
+
+use crate::{config::Config, error::SkyError, usage::Usage, Role};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ReportFormat {
+    #[default]
+    Markdown,
+    Json,
+    Html,
+}
+
+impl ReportFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ReportFormat::Markdown => "md",
+            ReportFormat::Json => "json",
+            ReportFormat::Html => "html",
+        }
+    }
+}
+
+/// Where and how to keep a transcript of the conversation.
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub format: ReportFormat,
+    /// File to write, or directory to write it in. The working directory when not given.
+    pub path: Option<PathBuf>,
+}
+
+impl Report {
+    /// The file to write, named after the time when it is only given a directory.
+    pub fn file(&self) -> Result<PathBuf, SkyError> {
+        let dir = match &self.path {
+            Some(path) if !path.is_dir() => return Ok(path.clone()),
+            Some(dir) => dir.as_path(),
+            None => Path::new("."),
+        };
+
+        let now = UNIX_EPOCH.elapsed().map_err(io::Error::other)?.as_millis();
+        Ok(dir.join(format!("chat-with-sky-{now}.{}", self.format.extension())))
+    }
+}
+
+/// Everything said in a conversation, kept whole even when the chat forgets older turns.
+#[derive(Debug, Clone, Serialize)]
+pub struct Transcript {
+    /// Seconds since the unix epoch.
+    pub started: u64,
+    pub provider: String,
+    pub parameters: Parameters,
+    pub messages: Vec<Entry>,
+    /// Everything spent on the conversation, including summaries of it.
+    pub usage: Usage,
+    /// Estimated in US dollars.
+    pub cost: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Parameters {
+    pub temperature: f32,
+    pub max_tokens: u32,
+    pub top_p: f32,
+    pub frequency_penalty: f32,
+    pub presence_penalty: f32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Entry {
+    pub role: Role,
+    pub content: String,
+    /// Seconds since the unix epoch.
+    pub time: u64,
+    /// Who answered, for the assistant's messages.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub persona: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// What the answer took, for the assistant's messages.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+}
+
+impl Transcript {
+    pub fn new(cfg: &Config) -> Self {
+        Self {
+            started: crate::session::now(),
+            provider: format!("{:?}", cfg.provider).to_lowercase(),
+            parameters: Parameters {
+                temperature: cfg.temperature,
+                max_tokens: cfg.max_tokens,
+                top_p: cfg.top_p,
+                frequency_penalty: cfg.frequency_penalty,
+                presence_penalty: cfg.presence_penalty,
+            },
+            messages: vec![],
+            usage: Usage::default(),
+            cost: 0.0,
+        }
+    }
+
+    pub fn render(&self, format: ReportFormat) -> Result<String, SkyError> {
+        Ok(match format {
+            ReportFormat::Markdown => self.markdown(),
+            ReportFormat::Json => serde_json::to_string_pretty(self)?,
+            ReportFormat::Html => self.html(),
+        })
+    }
+
+    /// Messages are written as they were said, so any code fences in them come through as is.
+    fn markdown(&self) -> String {
+        let mut md = format!(
+            "# Chat with Sky\n\n- Started: {}\n- Provider: {}\n- Temperature: {}, max tokens: {}\n",
+            utc(self.started),
+            self.provider,
+            self.parameters.temperature,
+            self.parameters.max_tokens,
+        );
+
+        for entry in &self.messages {
+            md.push_str(&format!(
+                "\n## {} ({})\n\n{}\n",
+                entry.label(),
+                utc(entry.time),
+                entry.content.trim()
+            ));
+        }
+
+        md.push_str(&format!("\n---\n\n{}\n", self.totals()));
+        md
+    }
+
+    fn totals(&self) -> String {
+        format!("{}, about ${:.4}", self.usage, self.cost)
+    }
+
+    fn html(&self) -> String {
+        let mut body = String::new();
+        for entry in &self.messages {
+            let class = match entry.role {
+                Role::User => "user",
+                _ => "assistant",
+            };
+            body.push_str(&format!(
+                "<section class=\"{class}\">\n<h2>{} <time>{}</time></h2>\n{}</section>\n",
+                escape(&entry.label()),
+                utc(entry.time),
+                html_content(&entry.content)
+            ));
+        }
+
+        format!(
+            r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Chat with Sky, {started}</title>
+<style>
+body {{ font-family: sans-serif; max-width: 50rem; margin: 2rem auto; padding: 0 1rem; line-height: 1.5; color: #222; }}
+header {{ color: #666; border-bottom: 1px solid #ddd; margin-bottom: 1rem; }}
+footer {{ color: #666; border-top: 1px solid #ddd; padding-top: 0.5rem; }}
+section {{ margin: 1rem 0; padding: 0.5rem 1rem; border-radius: 6px; }}
+section.user {{ background: #eef6fb; }}
+section.assistant {{ background: #f6f1fb; }}
+h2 {{ font-size: 1rem; margin: 0.25rem 0; }}
+time {{ font-weight: normal; color: #888; font-size: 0.85rem; }}
+pre {{ background: #272822; color: #f8f8f2; padding: 0.75rem; border-radius: 4px; overflow-x: auto; }}
+</style>
+</head>
+<body>
+<header>
+<h1>Chat with Sky</h1>
+<p>Started {started} · {provider} · temperature {temperature} · max tokens {max_tokens}</p>
+</header>
+{body}<footer>{totals}</footer>
+</body>
+</html>
+"#,
+            started = utc(self.started),
+            provider = escape(&self.provider),
+            temperature = self.parameters.temperature,
+            max_tokens = self.parameters.max_tokens,
+            totals = self.totals(),
+        )
+    }
+}
+
+impl Entry {
+    fn label(&self) -> String {
+        match (&self.role, &self.persona, &self.model) {
+            (Role::User, ..) => "You".to_string(),
+            (_, Some(persona), Some(model)) => format!("{persona} · {model}"),
+            (_, persona, _) => persona.clone().unwrap_or_else(|| "Sky".to_string()),
+        }
+    }
+}
+
+/// Paragraphs of text, with fenced code blocks kept apart in `<pre>`.
+fn html_content(content: &str) -> String {
+    let mut html = String::new();
+    let mut text = String::new();
+    let mut code: Option<(String, String)> = None;
+
+    let flush_text = |text: &mut String, html: &mut String| {
+        for paragraph in text.split("\n\n").filter(|p| !p.trim().is_empty()) {
+            html.push_str(&format!(
+                "<p>{}</p>\n",
+                escape(paragraph.trim()).replace('\n', "<br>\n")
+            ));
+        }
+        text.clear();
+    };
+
+    for line in content.trim().lines() {
+        match (&mut code, line.trim_start().strip_prefix("```")) {
+            (None, Some(lang)) => {
+                flush_text(&mut text, &mut html);
+                code = Some((lang.trim().to_string(), String::new()));
+            }
+            (Some(_), Some(_)) => {
+                let (lang, block) = code.take().unwrap_or_default();
+                html.push_str(&code_block(&lang, &block));
+            }
+            (Some((_, block)), None) => {
+                block.push_str(line);
+                block.push('\n');
+            }
+            (None, None) => {
+                text.push_str(line);
+                text.push('\n');
+            }
+        }
+    }
+
+    flush_text(&mut text, &mut html);
+    if let Some((lang, block)) = code {
+        html.push_str(&code_block(&lang, &block));
+    }
+
+    html
+}
+
+fn code_block(lang: &str, code: &str) -> String {
+    match lang {
+        "" => format!("<pre><code>{}</code></pre>\n", escape(code)),
+        lang => format!(
+            "<pre><code class=\"language-{}\">{}</code></pre>\n",
+            escape(lang),
+            escape(code)
+        ),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// `secs` since the unix epoch as a UTC date and time, like `2023-01-31 17:04:05 UTC`.
+fn utc(secs: u64) -> String {
+    let time = secs % 86_400;
+    format!(
+        "{} {:02}:{:02}:{:02} UTC",
+        date(secs),
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+/// `secs` since the unix epoch as a UTC date, like `2023-01-31`.
+pub(crate) fn date(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+
+    // Howard Hinnant's days_from_civil, run backwards.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{year:04}-{month:02}-{day:02}")
+}
@@ -0,0 +1,56 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    thread,
+    time::Duration,
+};
+
+use crate::config::Retry;
+
+/// Runs `request` until it succeeds, fails for good, or runs out of attempts,
+/// calling `notify` with the delay before sleeping on each retry.
+#[allow(clippy::result_large_err)] // ureq's error, handed back untouched
+pub fn with_backoff(
+    policy: &Retry,
+    mut notify: impl FnMut(Duration),
+    mut request: impl FnMut() -> Result<ureq::Response, ureq::Error>,
+) -> Result<ureq::Response, ureq::Error> {
+    let mut attempt = 1;
+    loop {
+        match request() {
+            Err(ureq::Error::Status(code, res))
+                if is_transient(code) && attempt < policy.max_attempts =>
+            {
+                let delay = retry_after(&res).unwrap_or_else(|| backoff(policy, attempt));
+                notify(delay);
+                thread::sleep(delay);
+                attempt += 1;
+            }
+            res => return res,
+        }
+    }
+}
+
+fn is_transient(code: u16) -> bool {
+    code == 429 || (500..600).contains(&code)
+}
+
+fn retry_after(res: &ureq::Response) -> Option<Duration> {
+    res.header("retry-after")?
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+/// Exponential backoff with jitter, picked between half and all of the capped delay.
+fn backoff(policy: &Retry, attempt: u32) -> Duration {
+    let delay = policy
+        .base_delay_ms
+        .saturating_mul(1 << (attempt - 1).min(16))
+        .min(policy.max_delay_ms);
+    let half = delay / 2;
+    let jitter = RandomState::new().build_hasher().finish() % (half + 1);
+
+    Duration::from_millis(half + jitter)
+}
@@ -0,0 +1,451 @@
+//! Full-screen chat interface.
+
+use std::{
+    cell::RefCell,
+    io::{self, stdout, Write},
+    iter,
+    rc::Rc,
+};
+
+use base64::Engine;
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use tui::{
+    backend::{Backend, CrosstermBackend},
+    layout::{Constraint, Direction, Layout},
+    style::{Color, Modifier, Style},
+    text::{Span, Spans},
+    widgets::{Block, Borders, Paragraph},
+    Terminal,
+};
+
+use crate::{context, error::SkyError, Chat, Message, Role};
+
+const MAX_INPUT_LINES: usize = 6;
+
+/// Runs the chat in the whole terminal until the user quits, showing the `notices` the chat
+/// sends about retries and trouble in the status line, as stderr can't be seen.
+pub fn run(chat: Box<dyn Chat>, notices: Notices) -> Result<(), SkyError> {
+    enable_raw_mode()?;
+    let _restore = Restore;
+    execute!(stdout(), EnterAlternateScreen)?;
+
+    let terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
+    run_on(terminal, chat, notices, iter::repeat_with(event::read))
+}
+
+/// Runs the chat on `terminal`, acting on `events` until the user quits or they run out.
+pub fn run_on<B: Backend + 'static>(
+    terminal: Terminal<B>,
+    mut chat: Box<dyn Chat>,
+    notices: Notices,
+    events: impl IntoIterator<Item = io::Result<Event>>,
+) -> Result<(), SkyError> {
+    let app = App {
+        notices: notices.clone(),
+        ..App::default()
+    };
+    let screen = Rc::new(RefCell::new(Screen { terminal, app }));
+    screen.borrow_mut().app.sync(chat.as_ref());
+
+    // A retry is waited out in the middle of a turn, so its notice has to be drawn then
+    // and there. When the screen is busy, the notice waits for its next draw.
+    let shown = Rc::downgrade(&screen);
+    notices.show_with(move || {
+        let Some(screen) = shown.upgrade() else {
+            return;
+        };
+        let Ok(mut screen) = screen.try_borrow_mut() else {
+            return;
+        };
+        screen.app.notice();
+        screen.draw().ok();
+    });
+
+    let mut events = events.into_iter();
+    loop {
+        let mut screen_now = screen.borrow_mut();
+        screen_now.app.notice();
+        screen_now.draw()?;
+        let app = &mut screen_now.app;
+
+        let Some(event) = events.next() else {
+            break;
+        };
+        let Event::Key(key) = event? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+
+        let question = match key {
+            KeyEvent {
+                code: KeyCode::Esc, ..
+            } => break,
+            KeyEvent {
+                code: KeyCode::Char('c' | 'd'),
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            } => break,
+            KeyEvent {
+                code: KeyCode::Enter,
+                modifiers: KeyModifiers::ALT,
+                ..
+            } => {
+                app.input.push('\n');
+                None
+            }
+            KeyEvent {
+                code: KeyCode::Enter,
+                ..
+            } if !app.input.trim().is_empty() => Some(std::mem::take(&mut app.input)),
+            KeyEvent {
+                code: KeyCode::Char('r'),
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            } => {
+                let text = chat.undo();
+                if text.is_none() {
+                    app.status = Some("nothing to resend".into());
+                }
+                text
+            }
+            KeyEvent {
+                code: KeyCode::Char('y'),
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            } => {
+                let last = app.history.iter().rev().find(|m| m.role == Role::Assistant);
+                app.status = Some(match last {
+                    Some(answer) => {
+                        copy(&answer.content)?;
+                        "copied the last answer".into()
+                    }
+                    None => "nothing to copy yet".into(),
+                });
+                None
+            }
+            KeyEvent {
+                code: KeyCode::Char('l'),
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            } => {
+                chat.clear();
+                app.sync(chat.as_ref());
+                app.status = Some("cleared the conversation".into());
+                None
+            }
+            KeyEvent {
+                code: KeyCode::PageUp,
+                ..
+            } => {
+                app.scroll += 10;
+                None
+            }
+            KeyEvent {
+                code: KeyCode::PageDown,
+                ..
+            } => {
+                app.scroll = app.scroll.saturating_sub(10);
+                None
+            }
+            KeyEvent {
+                code: KeyCode::Up,
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            } => {
+                app.scroll += 1;
+                None
+            }
+            KeyEvent {
+                code: KeyCode::Down,
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            } => {
+                app.scroll = app.scroll.saturating_sub(1);
+                None
+            }
+            KeyEvent {
+                code: KeyCode::Backspace,
+                ..
+            } => {
+                app.input.pop();
+                None
+            }
+            KeyEvent {
+                code: KeyCode::Char(c),
+                modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+                ..
+            } => {
+                app.input.push(c);
+                None
+            }
+            _ => None,
+        };
+
+        // The screen is let go of while the chat answers, so the notices can draw on it.
+        drop(screen_now);
+        if let Some(text) = question {
+            send(&screen, chat.as_mut(), text)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The notices about retries and trouble the chat sends while it is in full screen. They
+/// are shown in the status line as soon as they come in, even when the chat is waiting on
+/// an answer.
+#[derive(Clone, Default)]
+pub struct Notices(Rc<RefCell<Board>>);
+
+#[derive(Default)]
+struct Board {
+    unseen: Vec<String>,
+    /// Puts the unseen notices on the screen.
+    show: Option<Rc<dyn Fn()>>,
+}
+
+impl Notices {
+    pub fn send(&self, notice: String) {
+        self.0.borrow_mut().unseen.push(notice);
+        let show = self.0.borrow().show.clone();
+        if let Some(show) = show {
+            show();
+        }
+    }
+
+    fn show_with(&self, show: impl Fn() + 'static) {
+        self.0.borrow_mut().show = Some(Rc::new(show));
+    }
+
+    fn take(&self) -> Vec<String> {
+        std::mem::take(&mut self.0.borrow_mut().unseen)
+    }
+}
+
+struct Screen<B: Backend> {
+    terminal: Terminal<B>,
+    app: App,
+}
+
+impl<B: Backend> Screen<B> {
+    fn draw(&mut self) -> io::Result<()> {
+        draw(&mut self.terminal, &self.app)
+    }
+}
+
+#[derive(Default)]
+struct App {
+    input: String,
+    /// A copy of the conversation, so it can be drawn while the chat is busy answering.
+    history: Vec<Message>,
+    /// The question being answered, and as much of the answer as has arrived.
+    pending: Option<(String, String)>,
+    persona: String,
+    model: String,
+    tokens: usize,
+    /// How many lines the conversation is scrolled up from the bottom.
+    scroll: usize,
+    status: Option<String>,
+    notices: Notices,
+}
+
+impl App {
+    /// Puts the notices that came in since last time in the status line, after what is
+    /// there already.
+    fn notice(&mut self) {
+        let new = self.notices.take();
+        if new.is_empty() {
+            return;
+        }
+        let status = self.status.take().into_iter().chain(new);
+        self.status = Some(status.collect::<Vec<_>>().join(" · "));
+    }
+
+    fn sync(&mut self, chat: &dyn Chat) {
+        self.history = chat.history().to_vec();
+        self.persona = chat.persona().name.clone();
+        self.model = chat.model().to_string();
+        self.tokens = context::count_tokens(&self.history);
+    }
+
+    fn conversation(&self, width: usize) -> Vec<Spans<'static>> {
+        let said = self
+            .history
+            .iter()
+            .filter(|m| matches!(m.role, Role::User | Role::Assistant))
+            .filter(|m| !m.content.trim().is_empty())
+            .map(|m| (m.role, m.content.as_str()));
+        let pending = self.pending.iter().flat_map(|(question, answer)| {
+            [
+                (Role::User, question.as_str()),
+                (Role::Assistant, answer.as_str()),
+            ]
+        });
+
+        let mut lines = vec![];
+        for (role, content) in said.chain(pending) {
+            let (label, color) = match role {
+                Role::User => ("You", Color::Cyan),
+                _ => (self.persona.as_str(), Color::Magenta),
+            };
+
+            lines.push(Spans::from(Span::styled(
+                label.to_string(),
+                Style::default().fg(color).add_modifier(Modifier::BOLD),
+            )));
+            lines.extend(
+                content
+                    .trim()
+                    .split('\n')
+                    .flat_map(|line| wrap(line, width))
+                    .map(Spans::from),
+            );
+            lines.push(Spans::default());
+        }
+
+        lines
+    }
+}
+
+fn send<B: Backend>(
+    screen: &RefCell<Screen<B>>,
+    chat: &mut dyn Chat,
+    text: String,
+) -> io::Result<()> {
+    {
+        let mut screen = screen.borrow_mut();
+        let app = &mut screen.app;
+        app.scroll = 0;
+        app.status = Some(format!("waiting for {}...", app.persona));
+        app.pending = Some((text.clone(), String::new()));
+        screen.draw()?;
+    }
+
+    let res = chat.say_streaming(text, &mut |delta| {
+        let mut screen = screen.borrow_mut();
+        if let Some((_, answer)) = &mut screen.app.pending {
+            answer.push_str(delta);
+        }
+        screen.draw().ok();
+    });
+
+    let app = &mut screen.borrow_mut().app;
+    app.pending = None;
+    app.status = match res {
+        Ok(res) if res.truncated() => Some("the answer was cut off by max_tokens".into()),
+        Ok(_) => None,
+        Err(e) => Some(format!("error: {e}")),
+    };
+    app.notice();
+    app.sync(chat);
+
+    Ok(())
+}
+
+fn draw<B: Backend>(term: &mut Terminal<B>, app: &App) -> io::Result<()> {
+    term.draw(|f| {
+        let area = f.size();
+        let input_width = area.width.saturating_sub(2).max(1) as usize;
+        let input: Vec<String> = app
+            .input
+            .split('\n')
+            .flat_map(|line| wrap(line, input_width))
+            .collect();
+        let input_height = input.len().min(MAX_INPUT_LINES);
+
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Min(1),
+                Constraint::Length(input_height as u16 + 2),
+                Constraint::Length(1),
+            ])
+            .split(area);
+
+        let conversation = app.conversation(chunks[0].width.saturating_sub(2).max(1) as usize);
+        let height = chunks[0].height.saturating_sub(2) as usize;
+        let bottom = conversation.len().saturating_sub(height);
+        let top = bottom - app.scroll.min(bottom);
+        f.render_widget(
+            Paragraph::new(conversation)
+                .block(Block::default().borders(Borders::ALL).title(" sky "))
+                .scroll((top as u16, 0)),
+            chunks[0],
+        );
+
+        let shown = &input[input.len() - input_height..];
+        let cursor_line = shown.last().map_or(0, |line| line.chars().count());
+        f.render_widget(
+            Paragraph::new(shown.iter().cloned().map(Spans::from).collect::<Vec<_>>()).block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(" Enter to send, Alt+Enter for a new line "),
+            ),
+            chunks[1],
+        );
+        f.set_cursor(
+            chunks[1].x + 1 + cursor_line as u16,
+            chunks[1].y + input_height as u16,
+        );
+
+        let status = format!(
+            " {} · {} · ~{} tokens · {} · Ctrl+R resend · Ctrl+Y copy · Ctrl+L clear · Esc quit",
+            app.model,
+            app.persona,
+            app.tokens,
+            app.status.as_deref().unwrap_or("ready"),
+        );
+        f.render_widget(
+            Paragraph::new(status).style(Style::default().bg(Color::DarkGray).fg(Color::White)),
+            chunks[2],
+        );
+    })?;
+
+    Ok(())
+}
+
+/// Breaks `line` into lines no wider than `width`, at whitespace where possible.
+fn wrap(line: &str, width: usize) -> Vec<String> {
+    let mut lines = vec![String::new()];
+
+    for word in line.split_inclusive(' ') {
+        let current = lines.last_mut().expect("there is always a line");
+        if current.chars().count() + word.trim_end().chars().count() > width && !current.is_empty()
+        {
+            lines.push(String::new());
+        }
+
+        for c in word.chars() {
+            let current = lines.last_mut().expect("there is always a line");
+            if current.chars().count() >= width {
+                lines.push(String::new());
+            }
+            lines.last_mut().expect("there is always a line").push(c);
+        }
+    }
+
+    lines
+}
+
+/// Puts `text` on the clipboard through the terminal, with an OSC 52 escape sequence.
+fn copy(text: &str) -> io::Result<()> {
+    let encoded = base64::engine::general_purpose::STANDARD.encode(text);
+    write!(stdout(), "\x1b]52;c;{encoded}\x07")?;
+    stdout().flush()
+}
+
+/// Hands the terminal back the way it was found, even on a panic.
+struct Restore;
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        execute!(stdout(), LeaveAlternateScreen).ok();
+        disable_raw_mode().ok();
+    }
+}
@@ -0,0 +1,121 @@
+use std::{
+    fmt::Display,
+    fs, io,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::data_dir, dialogue, error::SkyError, Message};
+
+/// A named conversation, saved under the data directory so it can be picked up again later.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub name: String,
+    /// Seconds since the unix epoch.
+    pub created: u64,
+    pub updated: u64,
+    /// Name of the persona last chatted with.
+    #[serde(default)]
+    pub persona: Option<String>,
+    pub messages: Vec<Message>,
+}
+
+impl Session {
+    pub fn new(name: &str) -> Result<Self, SkyError> {
+        check_name(name)?;
+        let now = now();
+        Ok(Self {
+            name: name.to_string(),
+            created: now,
+            updated: now,
+            persona: None,
+            messages: vec![],
+        })
+    }
+
+    pub fn load(name: &str) -> Result<Self, SkyError> {
+        let path = path(name)?;
+        match fs::read_to_string(path) {
+            Ok(json) => Ok(serde_json::from_str(&json)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                Err(SkyError::NoSuchSession(name.to_string()))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Loads the session called `name`, or starts it if there is none yet.
+    pub fn load_or_new(name: &str) -> Result<Self, SkyError> {
+        match Self::load(name) {
+            Err(SkyError::NoSuchSession(_)) => Self::new(name),
+            res => res,
+        }
+    }
+
+    pub fn save(&mut self) -> Result<(), SkyError> {
+        self.updated = now();
+        fs::create_dir_all(dir()?)?;
+        fs::write(path(&self.name)?, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn delete(name: &str) -> Result<(), SkyError> {
+        match fs::remove_file(path(name)?) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                Err(SkyError::NoSuchSession(name.to_string()))
+            }
+            res => Ok(res?),
+        }
+    }
+
+    /// Every saved session, most recently used first.
+    pub fn list() -> Result<Vec<Self>, SkyError> {
+        let entries = match fs::read_dir(dir()?) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            entries => entries?,
+        };
+
+        let mut sessions = vec![];
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                sessions.push(serde_json::from_str::<Self>(&fs::read_to_string(path)?)?);
+            }
+        }
+        sessions.sort_by_key(|s| std::cmp::Reverse(s.updated));
+
+        Ok(sessions)
+    }
+}
+
+impl Display for Session {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let label = self.persona.as_deref().unwrap_or("Sky");
+        write!(f, "{}", dialogue(&self.messages, label))
+    }
+}
+
+fn dir() -> Result<PathBuf, SkyError> {
+    Ok(data_dir()?.join("sessions"))
+}
+
+fn path(name: &str) -> Result<PathBuf, SkyError> {
+    check_name(name)?;
+    Ok(dir()?.join(format!("{name}.json")))
+}
+
+fn check_name(name: &str) -> Result<(), SkyError> {
+    match name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        true => Err(SkyError::BadSessionName(name.to_string())),
+        false => Ok(()),
+    }
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
@@ -0,0 +1,412 @@
+//! Tools the model can call to look around the working directory and run commands in it.
+
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::{BufRead, BufReader, Read, Write},
+    path::PathBuf,
+    process::{Command, Stdio},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{attach, config};
+
+/// Most of a tool's output given back to the model, in bytes.
+const MAX_OUTPUT: usize = 16_000;
+
+/// Most lines `grep` gives back.
+const MAX_MATCHES: usize = 200;
+
+/// A call the assistant makes to one of the tools, as OpenAI puts it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type", default = "function")]
+    pub kind: String,
+    pub function: FunctionCall,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FunctionCall {
+    pub name: String,
+    /// A JSON object, as a string.
+    pub arguments: String,
+}
+
+fn function() -> String {
+    "function".to_string()
+}
+
+impl Default for ToolCall {
+    fn default() -> Self {
+        Self {
+            id: String::new(),
+            kind: function(),
+            function: FunctionCall::default(),
+        }
+    }
+}
+
+impl ToolCall {
+    /// The tool and its arguments on one line, for telling the user what is about to run.
+    pub fn describe(&self) -> String {
+        format!("{} {}", self.function.name, self.function.arguments.trim())
+    }
+}
+
+/// A tool as the model is told about it, `parameters` being a JSON schema.
+#[derive(Debug, Clone, Serialize)]
+pub struct Definition {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub parameters: Value,
+}
+
+/// Decides whether a call may run, given whether its tool has side effects.
+pub type Permit = Box<dyn Fn(&ToolCall, bool) -> bool>;
+
+/// Does what a call asks for with its arguments, or says what went wrong.
+type Handler = Box<dyn Fn(&config::Tools, &Value) -> Result<String, String>>;
+
+struct Tool {
+    definition: Definition,
+    /// Whether it changes anything, in which case the user is asked before it runs.
+    side_effects: bool,
+    handler: Handler,
+}
+
+/// The tools the model may call, looked up by name.
+#[derive(Default)]
+pub struct Toolbox {
+    tools: Vec<Tool>,
+}
+
+impl Toolbox {
+    pub fn register(
+        &mut self,
+        definition: Definition,
+        side_effects: bool,
+        handler: impl Fn(&config::Tools, &Value) -> Result<String, String> + 'static,
+    ) {
+        self.tools.retain(|t| t.definition.name != definition.name);
+        self.tools.push(Tool {
+            definition,
+            side_effects,
+            handler: Box::new(handler),
+        });
+    }
+
+    pub fn definitions(&self) -> Vec<Definition> {
+        self.tools.iter().map(|t| t.definition.clone()).collect()
+    }
+
+    /// Whether the tool `name` changes anything. Unknown tools don't, as they never run.
+    pub fn side_effects(&self, name: &str) -> bool {
+        self.find(name).is_some_and(|t| t.side_effects)
+    }
+
+    /// Runs `call`, giving back what the tool had to say, or what went wrong, for the model.
+    pub fn call(&self, cfg: &config::Tools, call: &ToolCall) -> String {
+        let Some(tool) = self.find(&call.function.name) else {
+            return format!("error: there is no tool called {}", call.function.name);
+        };
+        let args = match call.function.arguments.trim() {
+            "" => Ok(json!({})),
+            args => serde_json::from_str(args),
+        };
+
+        match args.map_err(|e| format!("the arguments aren't valid JSON: {e}")) {
+            Ok(args) => match (tool.handler)(cfg, &args) {
+                Ok(output) => capped(output),
+                Err(e) => format!("error: {e}"),
+            },
+            Err(e) => format!("error: {e}"),
+        }
+    }
+
+    fn find(&self, name: &str) -> Option<&Tool> {
+        self.tools.iter().find(|t| t.definition.name == name)
+    }
+
+    pub fn builtin() -> Self {
+        let mut tools = Self::default();
+
+        tools.register(
+            Definition {
+                name: "read_file",
+                description: "Read a text file in the working directory.",
+                parameters: json!({
+                    "type": "object",
+                    "properties": {
+                        "path": { "type": "string", "description": "Path relative to the working directory" }
+                    },
+                    "required": ["path"]
+                }),
+            },
+            false,
+            |_, args| {
+                let path = within_cwd(arg(args, "path")?)?;
+                let bytes = fs::read(&path).map_err(|e| format!("{}: {e}", path.display()))?;
+                match bytes.iter().take(8000).any(|&b| b == 0) {
+                    true => Err(format!("{} isn't text", path.display())),
+                    false => Ok(String::from_utf8_lossy(&bytes).into_owned()),
+                }
+            },
+        );
+        tools.register(
+            Definition {
+                name: "list_directory",
+                description: "List a directory in the working directory, marking subdirectories with a trailing slash.",
+                parameters: json!({
+                    "type": "object",
+                    "properties": {
+                        "path": { "type": "string", "description": "Path relative to the working directory, . by default" }
+                    }
+                }),
+            },
+            false,
+            |_, args| {
+                let path = within_cwd(args["path"].as_str().unwrap_or("."))?;
+                let entries = fs::read_dir(&path).map_err(|e| format!("{}: {e}", path.display()))?;
+
+                let mut names: Vec<String> = entries
+                    .filter_map(Result::ok)
+                    .map(|e| {
+                        let name = e.file_name().to_string_lossy().into_owned();
+                        match e.path().is_dir() {
+                            true => format!("{name}/"),
+                            false => name,
+                        }
+                    })
+                    .collect();
+                names.sort();
+                Ok(names.join("\n"))
+            },
+        );
+        tools.register(
+            Definition {
+                name: "grep",
+                description: "Search files in the working directory for lines matching a regular expression, leaving out those git ignores.",
+                parameters: json!({
+                    "type": "object",
+                    "properties": {
+                        "pattern": { "type": "string", "description": "Regular expression, in Rust's regex syntax" },
+                        "path": { "type": "string", "description": "File, directory or glob like src/**/*.rs to search, . by default" }
+                    },
+                    "required": ["pattern"]
+                }),
+            },
+            false,
+            |_, args| {
+                let regex = regex::Regex::new(arg(args, "pattern")?).map_err(|e| e.to_string())?;
+                let path = args["path"].as_str().unwrap_or(".");
+                // Checked before expanding it, so a path like / isn't walked at all.
+                within_cwd(&attach::glob_base(path).to_string_lossy())?;
+                let files: Vec<PathBuf> = attach::expand(path)
+                    .map_err(|e| e.to_string())?
+                    .into_iter()
+                    .filter(|file| within_cwd(&file.to_string_lossy()).is_ok())
+                    .collect();
+                if files.is_empty() {
+                    return Err(format!("{path} is outside the working directory"));
+                }
+
+                let mut matches = vec![];
+                for file in files {
+                    let Ok(text) = fs::read_to_string(&file) else {
+                        continue;
+                    };
+                    for (n, line) in text.lines().enumerate() {
+                        if regex.is_match(line) {
+                            matches.push(format!("{}:{}: {line}", attach::slashed(&file), n + 1));
+                        }
+                    }
+                }
+
+                Ok(match matches.len() {
+                    0 => "no matches".to_string(),
+                    n if n > MAX_MATCHES => {
+                        matches.truncate(MAX_MATCHES);
+                        format!("{}\n... ({} more matches)", matches.join("\n"), n - MAX_MATCHES)
+                    }
+                    _ => matches.join("\n"),
+                })
+            },
+        );
+        tools.register(
+            Definition {
+                name: "run_command",
+                description: "Run a command in the working directory, without a shell, and get its exit status and output. Only some programs are allowed.",
+                parameters: json!({
+                    "type": "object",
+                    "properties": {
+                        "command": { "type": "string", "description": "The program and its arguments, like `cargo test`" }
+                    },
+                    "required": ["command"]
+                }),
+            },
+            true,
+            |cfg, args| {
+                let command = arg(args, "command")?;
+                if command.contains(['|', '&', ';', '<', '>', '$', '`', '(', ')', '\n']) {
+                    return Err(
+                        "commands run without a shell, so pipes, redirections, variables and the like can't be used"
+                            .to_string(),
+                    );
+                }
+
+                let words = words(command)?;
+                let Some(program) = words.first() else {
+                    return Err("the command is empty".to_string());
+                };
+                if !cfg.allowed_commands.contains(program) {
+                    return Err(format!(
+                        "{program} isn't allowed, only {} are",
+                        cfg.allowed_commands.join(", ")
+                    ));
+                }
+
+                run(&words, Duration::from_secs(cfg.command_timeout_secs))
+            },
+        );
+
+        tools
+    }
+}
+
+/// Asks the user a yes or no question on the terminal, even when stdin is taken by a pipe.
+/// Anything but yes is no, as is there being no terminal to ask on.
+pub fn confirm(question: &str) -> bool {
+    let Ok(mut tty) = OpenOptions::new().read(true).write(true).open("/dev/tty") else {
+        return false;
+    };
+    if write!(tty, "{question} [y/N] ")
+        .and_then(|_| tty.flush())
+        .is_err()
+    {
+        return false;
+    }
+
+    let mut answer = String::new();
+    match BufReader::new(tty).read_line(&mut answer) {
+        Ok(_) => matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"),
+        Err(_) => false,
+    }
+}
+
+fn arg<'a>(args: &'a Value, name: &str) -> Result<&'a str, String> {
+    args[name]
+        .as_str()
+        .ok_or_else(|| format!("the {name} argument is missing"))
+}
+
+/// `path` if it is within the working directory, which is as far as the tools may look.
+fn within_cwd(path: &str) -> Result<PathBuf, String> {
+    let cwd = env::current_dir()
+        .and_then(|dir| dir.canonicalize())
+        .map_err(|e| e.to_string())?;
+    let full = cwd
+        .join(path)
+        .canonicalize()
+        .map_err(|e| format!("{path}: {e}"))?;
+
+    match full.starts_with(&cwd) {
+        true => Ok(PathBuf::from(path)),
+        false => Err(format!("{path} is outside the working directory")),
+    }
+}
+
+/// Splits `command` into words at whitespace, keeping what is in single or double quotes together.
+fn words(command: &str) -> Result<Vec<String>, String> {
+    let mut words = vec![];
+    let mut word = None::<String>;
+    let mut quote = None;
+
+    for c in command.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    match quote {
+        Some(q) => Err(format!("the command has an unclosed {q}")),
+        None => {
+            words.extend(word);
+            Ok(words)
+        }
+    }
+}
+
+/// Runs the program `words` start with, killing it after `timeout`.
+fn run(words: &[String], timeout: Duration) -> Result<String, String> {
+    let mut child = Command::new(&words[0])
+        .args(&words[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("couldn't run {}: {e}", words[0]))?;
+    let stdout = child.stdout.take().map(read_in_background);
+    let stderr = child.stderr.take().map(read_in_background);
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() >= deadline => {
+                child.kill().ok();
+                child.wait().ok();
+                return Err(format!(
+                    "{} was killed after running for {}s",
+                    words[0],
+                    timeout.as_secs()
+                ));
+            }
+            Ok(None) => thread::sleep(Duration::from_millis(20)),
+            Err(e) => return Err(e.to_string()),
+        }
+    };
+
+    let output =
+        |reader: Option<JoinHandle<String>>| reader.and_then(|r| r.join().ok()).unwrap_or_default();
+    Ok(format!(
+        "{status}\n--- stdout ---\n{}\n--- stderr ---\n{}",
+        output(stdout).trim_end(),
+        output(stderr).trim_end()
+    ))
+}
+
+fn read_in_background(mut reader: impl Read + Send + 'static) -> JoinHandle<String> {
+    thread::spawn(move || {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes).ok();
+        String::from_utf8_lossy(&bytes).into_owned()
+    })
+}
+
+/// `output` cut down to [`MAX_OUTPUT`] bytes, saying how much was left out.
+fn capped(mut output: String) -> String {
+    if output.len() <= MAX_OUTPUT {
+        return output;
+    }
+
+    let mut end = MAX_OUTPUT;
+    while !output.is_char_boundary(end) {
+        end -= 1;
+    }
+    let left_out = output.len() - end;
+    output.truncate(end);
+    output.push_str(&format!("\n... (cut short, {left_out} more bytes)"));
+    output
+}
@@ -0,0 +1,194 @@
+//! Counting the tokens spent, and what they cost.
+
+use std::{fmt::Display, fs, io, ops::AddAssign, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{data_dir, Config},
+    error::SkyError,
+    report, session,
+};
+
+/// Tokens spent on a request, as the API reports them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Usage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub total_tokens: u32,
+}
+
+impl Usage {
+    pub fn new(prompt_tokens: u32, completion_tokens: u32) -> Self {
+        Self {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        }
+    }
+
+    /// Takes in what another event of a streamed answer says was spent, which
+    /// may be only part of the story or a larger count of the same.
+    pub fn merge(&mut self, other: Usage) {
+        *self = Usage::new(
+            self.prompt_tokens.max(other.prompt_tokens),
+            self.completion_tokens.max(other.completion_tokens),
+        );
+    }
+}
+
+impl AddAssign for Usage {
+    fn add_assign(&mut self, other: Self) {
+        *self = Usage::new(
+            self.prompt_tokens + other.prompt_tokens,
+            self.completion_tokens + other.completion_tokens,
+        );
+    }
+}
+
+impl Display for Usage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} prompt + {} completion = {} tokens",
+            self.prompt_tokens, self.completion_tokens, self.total_tokens
+        )
+    }
+}
+
+/// What a model charges, in US dollars per million tokens.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Price {
+    pub prompt: f64,
+    pub completion: f64,
+}
+
+impl Price {
+    pub fn cost(&self, usage: Usage) -> f64 {
+        (usage.prompt_tokens as f64 * self.prompt
+            + usage.completion_tokens as f64 * self.completion)
+            / 1_000_000.0
+    }
+}
+
+/// Openai's list prices for prompt and completion, for when the config doesn't say.
+const PRICES: &[(&str, f64, f64)] = &[
+    ("gpt-3.5-turbo", 0.5, 1.5),
+    ("gpt-4", 30.0, 60.0),
+    ("gpt-4-turbo", 10.0, 30.0),
+    ("gpt-4o", 2.5, 10.0),
+    ("gpt-4o-mini", 0.15, 0.6),
+];
+
+/// The price of `model`, from the config or else the list prices. Dated snapshots like
+/// `gpt-4o-2024-08-06` go by the price of the longest name they start with.
+pub fn price(cfg: &Config, model: &str) -> Option<Price> {
+    let configured = cfg
+        .prices
+        .iter()
+        .map(|(name, price)| (name.as_str(), *price));
+    let builtin = PRICES
+        .iter()
+        .map(|&(name, prompt, completion)| (name, Price { prompt, completion }));
+
+    // The last of equally long names wins, which makes it the configured one.
+    builtin
+        .chain(configured)
+        .filter(|(name, _)| model.starts_with(name))
+        .max_by_key(|(name, _)| name.len())
+        .map(|(_, price)| price)
+}
+
+/// What has been spent over a conversation.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Spending {
+    /// What the last answer took.
+    pub last: Usage,
+    pub total: Usage,
+    /// Estimated in US dollars.
+    pub cost: f64,
+    /// Some tokens went to models without a known price, so the cost falls short.
+    pub unpriced: bool,
+}
+
+impl Spending {
+    /// Counts a request to `model`, giving back its cost.
+    pub fn add(&mut self, cfg: &Config, model: &str, usage: Usage) -> f64 {
+        self.total += usage;
+        match price(cfg, model) {
+            Some(price) => {
+                let cost = price.cost(usage);
+                self.cost += cost;
+                cost
+            }
+            None => {
+                self.unpriced |= usage.total_tokens > 0;
+                0.0
+            }
+        }
+    }
+}
+
+/// What was spent today, across every conversation, kept under the data directory.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Ledger {
+    /// The UTC date, like `2023-01-31`.
+    date: String,
+    cost: f64,
+}
+
+fn ledger_path() -> Result<PathBuf, SkyError> {
+    Ok(data_dir()?.join("spending.json"))
+}
+
+fn today() -> String {
+    report::date(session::now())
+}
+
+/// US dollars spent today.
+pub fn spent_today() -> Result<f64, SkyError> {
+    let ledger: Ledger = match fs::read_to_string(ledger_path()?) {
+        Ok(json) => serde_json::from_str(&json)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ledger::default(),
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok(match ledger.date == today() {
+        true => ledger.cost,
+        false => 0.0,
+    })
+}
+
+/// Adds `cost` to what was spent today.
+pub fn spend(cost: f64) -> Result<(), SkyError> {
+    if cost <= 0.0 {
+        return Ok(());
+    }
+
+    let ledger = Ledger {
+        date: today(),
+        cost: spent_today()? + cost,
+    };
+    let path = ledger_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, serde_json::to_string(&ledger)?)?;
+
+    Ok(())
+}
+
+/// Refuses to go on once today's spending has reached the configured budget.
+pub fn check_budget(cfg: &Config) -> Result<(), SkyError> {
+    let Some(budget) = cfg.daily_budget else {
+        return Ok(());
+    };
+
+    let spent = spent_today()?;
+    match spent >= budget {
+        true => Err(SkyError::OverBudget { spent, budget }),
+        false => Ok(()),
+    }
+}
@@ -0,0 +1,480 @@
+//! The `Chat` implementations, driven through the mock provider.
+
+use std::{cell::RefCell, fs, rc::Rc};
+
+use serde_json::{json, Value};
+use sky::{
+    config::{Config, ContextStrategy, MockMode},
+    error::SkyError,
+    provider::ProviderKind,
+    report::{Report, ReportFormat},
+    *,
+};
+
+fn config(mode: MockMode, path: Option<String>) -> Config {
+    let mut cfg = Config {
+        provider: ProviderKind::Mock,
+        model: "mock".to_string(),
+        ..Config::default()
+    };
+    cfg.mock.mode = mode;
+    cfg.mock.path = path;
+    cfg
+}
+
+fn echo() -> ChatWithAI {
+    ChatWithAI::new(config(MockMode::Echo, None)).unwrap()
+}
+
+/// A config answering from a script of `answers`, kept in `dir`.
+fn script(dir: &tempfile::TempDir, answers: &[&str]) -> Config {
+    let path = dir.path().join("script.txt");
+    fs::write(&path, answers.join("\n---\n")).unwrap();
+    config(MockMode::Script, Some(path.to_string_lossy().into_owned()))
+}
+
+fn scripted(dir: &tempfile::TempDir, answers: &[&str]) -> ChatWithAI {
+    ChatWithAI::new(script(dir, answers)).unwrap()
+}
+
+fn said(chat: &dyn Chat) -> Vec<(Role, String)> {
+    chat.history()
+        .iter()
+        .map(|m| (m.role, m.content.clone()))
+        .collect()
+}
+
+const LONG_QUESTION: &str = "What is the weather going to be like this week, day by day?";
+const LONG_ANSWER: &str = "Monday will be sunny and warm, Tuesday cloudy with a light breeze, \
+    Wednesday wet in the morning and clearing later, and Thursday cold with frost overnight.";
+
+/// Exactly enough room for the system prompt, the long first turn and a short question.
+fn room_for_the_first_turn() -> usize {
+    let system = echo().history()[0].clone();
+    context::count_tokens(&[
+        system,
+        Message::user(LONG_QUESTION),
+        Message::assistant(LONG_ANSWER),
+        Message::user("tomorrow?"),
+    ])
+}
+
+fn roles(chat: &dyn Chat) -> Vec<Role> {
+    chat.history().iter().map(|m| m.role).collect()
+}
+
+#[test]
+fn say_adds_the_turn_to_the_history() {
+    let mut chat = echo();
+
+    let res = chat.say("hello there".to_string()).unwrap();
+
+    assert_eq!(res.to_string(), "hello there");
+    assert_eq!(roles(&chat), [Role::System, Role::User, Role::Assistant]);
+    assert_eq!(chat.history()[2].content, "hello there");
+}
+
+#[test]
+fn say_streaming_hands_over_the_whole_answer() {
+    let mut chat = echo();
+    let mut streamed = String::new();
+
+    let res = chat
+        .say_streaming("one two three".to_string(), &mut |delta| {
+            streamed.push_str(delta)
+        })
+        .unwrap();
+
+    assert_eq!(streamed, "one two three");
+    assert_eq!(res.to_string(), "one two three");
+    assert!(!res.truncated());
+}
+
+#[test]
+fn the_dialogue_leaves_out_the_system_prompt() {
+    let mut chat = echo();
+    chat.say("hi".to_string()).unwrap();
+    chat.say("bye".to_string()).unwrap();
+
+    assert_eq!(chat.to_string(), "You: hi\nSky: hi\nYou: bye\nSky: bye\n");
+}
+
+#[test]
+fn undo_and_clear_forget_turns() {
+    let mut chat = echo();
+    chat.say("first".to_string()).unwrap();
+    chat.say("second".to_string()).unwrap();
+
+    assert_eq!(chat.undo().as_deref(), Some("second"));
+    assert_eq!(chat.history().len(), 3);
+
+    chat.clear();
+    assert_eq!(roles(&chat), [Role::System]);
+    assert_eq!(chat.undo(), None);
+}
+
+#[test]
+fn a_failed_turn_is_forgotten() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut chat = scripted(&dir, &["only answer"]);
+    chat.say("one".to_string()).unwrap();
+
+    let err = chat.say("two".to_string()).unwrap_err();
+
+    assert!(matches!(err, SkyError::MockExhausted(_)), "{err}");
+    assert_eq!(chat.history().len(), 3);
+}
+
+#[test]
+fn choices_come_from_the_script_and_one_can_be_picked() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut chat = scripted(&dir, &["red", "green", "blue"]);
+
+    let res = chat.say_choices("a colour?".to_string(), 3).unwrap();
+
+    let texts: Vec<&str> = res.choices().map(|c| c.text()).collect();
+    assert_eq!(texts, ["red", "green", "blue"]);
+    assert_eq!(chat.history()[2].content, "red");
+
+    chat.choose(2).unwrap();
+    assert_eq!(chat.history()[2].content, "blue");
+    assert!(matches!(chat.choose(3), Err(SkyError::NoSuchChoice(4))));
+}
+
+#[test]
+fn alternatives_are_forgotten_with_their_question() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut chat = scripted(&dir, &["first", "a", "b"]);
+    chat.say("q0".to_string()).unwrap();
+    chat.say_choices("q1".to_string(), 2).unwrap();
+
+    chat.undo();
+
+    assert!(matches!(chat.choose(1), Err(SkyError::NoSuchChoice(2))));
+    assert_eq!(chat.history()[2].content, "first");
+    assert!(matches!(
+        chat.choose(usize::MAX),
+        Err(SkyError::NoSuchChoice(usize::MAX))
+    ));
+}
+
+#[test]
+fn a_cut_off_answer_can_be_continued() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut cfg = config(MockMode::Script, None);
+    let script = dir.path().join("script.txt");
+    fs::write(&script, "one two three four five six\n---\nseven eight").unwrap();
+    cfg.mock.path = Some(script.to_string_lossy().into_owned());
+    cfg.max_tokens = 3;
+    let mut chat = ChatWithAI::new(cfg).unwrap();
+
+    let res = chat.say("count".to_string()).unwrap();
+    assert!(res.truncated());
+    assert_eq!(res.to_string(), "one two three");
+
+    let more = chat.continue_answer(&mut |_| {}).unwrap();
+    assert!(!more.truncated());
+    assert_eq!(chat.history()[2].content, "one two three seven eight");
+    assert_eq!(chat.history().len(), 3);
+}
+
+#[test]
+fn there_is_nothing_to_continue_before_an_answer() {
+    let mut chat = echo();
+
+    let err = chat.continue_answer(&mut |_| {}).unwrap_err();
+
+    assert!(matches!(err, SkyError::NothingToContinue));
+}
+
+#[test]
+fn spending_adds_up_the_usage() {
+    let mut chat = echo();
+    chat.say("hello".to_string()).unwrap();
+    let first = chat.spending().total;
+    chat.say("hello".to_string()).unwrap();
+
+    let spending = chat.spending();
+    assert!(first.total_tokens > 0);
+    assert!(spending.total.total_tokens > first.total_tokens);
+    assert_eq!(spending.last.completion_tokens, 1);
+    assert!(spending.unpriced);
+}
+
+#[test]
+fn the_oldest_turns_are_dropped_to_fit_the_prompt() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut cfg = script(&dir, &[LONG_ANSWER, "Rain.", "Snow."]);
+    cfg.context.strategy = ContextStrategy::DropOldest;
+    cfg.context.max_prompt_tokens = room_for_the_first_turn();
+    let mut chat = ChatWithAI::new(cfg).unwrap();
+
+    chat.say(LONG_QUESTION.to_string()).unwrap();
+    chat.say("tomorrow?".to_string()).unwrap();
+    assert_eq!(chat.history().len(), 5);
+    chat.say("and after?".to_string()).unwrap();
+
+    assert_eq!(
+        said(&chat)[1..],
+        [
+            (Role::User, "tomorrow?".to_string()),
+            (Role::Assistant, "Rain.".to_string()),
+            (Role::User, "and after?".to_string()),
+            (Role::Assistant, "Snow.".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn a_sliding_window_keeps_only_the_last_turns() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut cfg = script(&dir, &["one", "two", "three"]);
+    cfg.context.strategy = ContextStrategy::SlidingWindow;
+    cfg.context.window_turns = 1;
+    let mut chat = ChatWithAI::new(cfg).unwrap();
+
+    chat.say("first".to_string()).unwrap();
+    chat.say("second".to_string()).unwrap();
+    chat.say("third".to_string()).unwrap();
+
+    assert_eq!(
+        said(&chat)[1..],
+        [
+            (Role::User, "second".to_string()),
+            (Role::Assistant, "two".to_string()),
+            (Role::User, "third".to_string()),
+            (Role::Assistant, "three".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn the_oldest_turns_are_summarized_to_fit_the_prompt() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut cfg = script(&dir, &[LONG_ANSWER, "Rain.", "A week of weather.", "Snow."]);
+    cfg.context.strategy = ContextStrategy::Summarize;
+    cfg.context.max_prompt_tokens = room_for_the_first_turn();
+    let mut chat = ChatWithAI::new(cfg).unwrap();
+
+    chat.say(LONG_QUESTION.to_string()).unwrap();
+    chat.say("tomorrow?".to_string()).unwrap();
+    chat.say("and after?".to_string()).unwrap();
+
+    assert_eq!(
+        said(&chat)[1..],
+        [
+            (
+                Role::System,
+                "Summary of the earlier conversation: A week of weather.".to_string()
+            ),
+            (Role::User, "tomorrow?".to_string()),
+            (Role::Assistant, "Rain.".to_string()),
+            (Role::User, "and after?".to_string()),
+            (Role::Assistant, "Snow.".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn fixtures_are_served_as_recorded() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(
+        dir.path().join("0001.response"),
+        json!({
+            "choices": [{ "message": { "role": "assistant", "content": "recorded" }, "finish_reason": "stop" }],
+            "usage": { "prompt_tokens": 7, "completion_tokens": 1, "total_tokens": 8 }
+        })
+        .to_string(),
+    )
+    .unwrap();
+    let path = dir.path().to_string_lossy().into_owned();
+    let mut chat = ChatWithAI::new(config(MockMode::Fixtures, Some(path))).unwrap();
+
+    let res = chat.say("anything".to_string()).unwrap();
+
+    assert_eq!(res.to_string(), "recorded");
+    assert_eq!(chat.spending().last.total_tokens, 8);
+    assert!(matches!(
+        chat.say("more".to_string()),
+        Err(SkyError::MockExhausted(_))
+    ));
+}
+
+#[test]
+fn tool_calls_run_until_there_is_an_answer() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(
+        dir.path().join("0001.response"),
+        json!({
+            "choices": [{
+                "message": {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": { "name": "list_directory", "arguments": "{\"path\": \"src\"}" }
+                    }]
+                },
+                "finish_reason": "tool_calls"
+            }]
+        })
+        .to_string(),
+    )
+    .unwrap();
+    fs::write(
+        dir.path().join("0002.response"),
+        "data: {\"choices\":[{\"delta\":{\"content\":\"there is a lib.rs\"},\"finish_reason\":\"stop\"}]}\n\ndata: [DONE]\n\n",
+    )
+    .unwrap();
+    let mut cfg = config(
+        MockMode::Fixtures,
+        Some(dir.path().to_string_lossy().into_owned()),
+    );
+    cfg.tools.enabled = true;
+    let mut chat = ChatWithAI::new(cfg).unwrap();
+
+    let res = chat.say("what is in src?".to_string()).unwrap();
+
+    assert_eq!(res.to_string(), "there is a lib.rs");
+    assert_eq!(
+        roles(&chat),
+        [
+            Role::System,
+            Role::User,
+            Role::Assistant,
+            Role::Tool,
+            Role::Assistant
+        ]
+    );
+    let listing = &chat.history()[3];
+    assert_eq!(listing.tool_call_id.as_deref(), Some("call_1"));
+    assert!(listing.content.lines().any(|name| name == "lib.rs"));
+}
+
+#[test]
+fn grep_stays_in_the_working_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    let call = |id: &str, path: &str| {
+        json!({
+            "id": id,
+            "type": "function",
+            "function": {
+                "name": "grep",
+                "arguments": json!({ "pattern": "^name", "path": path }).to_string()
+            }
+        })
+    };
+    fs::write(
+        dir.path().join("0001.response"),
+        json!({
+            "choices": [{
+                "message": {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [call("call_1", "/"), call("call_2", "*.toml")]
+                },
+                "finish_reason": "tool_calls"
+            }]
+        })
+        .to_string(),
+    )
+    .unwrap();
+    fs::write(
+        dir.path().join("0002.response"),
+        "data: {\"choices\":[{\"delta\":{\"content\":\"done\"},\"finish_reason\":\"stop\"}]}\n\ndata: [DONE]\n\n",
+    )
+    .unwrap();
+    let mut cfg = config(
+        MockMode::Fixtures,
+        Some(dir.path().to_string_lossy().into_owned()),
+    );
+    cfg.tools.enabled = true;
+    let mut chat = ChatWithAI::new(cfg).unwrap();
+
+    chat.say_choices("where is the name?".to_string(), 1)
+        .unwrap();
+
+    let outside = &chat.history()[3].content;
+    assert_eq!(outside, "error: / is outside the working directory");
+    let found = &chat.history()[4].content;
+    assert_eq!(found, "Cargo.toml:2: name = \"sky\"");
+}
+
+#[test]
+fn reports_are_written_after_every_turn() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("chat.json");
+    let report = Report {
+        format: ReportFormat::Json,
+        path: Some(path.clone()),
+    };
+    let mut chat = chat_factory(config(MockMode::Echo, None), None, None)
+        .unwrap()
+        .reporting(report)
+        .unwrap();
+
+    chat.say("first".to_string()).unwrap();
+    chat.say("second".to_string()).unwrap();
+
+    let transcript: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    let messages = transcript["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), 4);
+    assert_eq!(messages[0]["role"], "user");
+    assert_eq!(messages[1]["content"], "first");
+    assert_eq!(messages[1]["persona"], "Sky");
+    assert_eq!(messages[3]["model"], "mock");
+    assert!(transcript["usage"]["total_tokens"].as_u64().unwrap() > 0);
+}
+
+#[test]
+fn trouble_writing_the_report_is_a_warning() {
+    let dir = tempfile::tempdir().unwrap();
+    let report = Report {
+        format: ReportFormat::Json,
+        path: Some(dir.path().join("chat.json")),
+    };
+    let warnings = Rc::new(RefCell::new(vec![]));
+    let warned = warnings.clone();
+    let mut chat = chat_factory(config(MockMode::Echo, None), None, None)
+        .unwrap()
+        .on_warning(move |warning| warned.borrow_mut().push(warning.to_string()))
+        .reporting(report)
+        .unwrap();
+    dir.close().unwrap();
+
+    chat.say("hello".to_string()).unwrap();
+
+    assert_eq!(warnings.borrow().len(), 1);
+    assert!(warnings.borrow()[0].contains("No such file"));
+}
+
+#[test]
+fn markdown_reports_keep_up_with_picked_answers() {
+    let dir = tempfile::tempdir().unwrap();
+    let script = dir.path().join("script.txt");
+    fs::write(&script, "plain\n---\nfancy").unwrap();
+    let path = dir.path().join("chat.md");
+    let report = Report {
+        format: ReportFormat::Markdown,
+        path: Some(path.clone()),
+    };
+    let cfg = config(
+        MockMode::Script,
+        Some(script.to_string_lossy().into_owned()),
+    );
+    let mut chat = chat_factory(cfg, None, None)
+        .unwrap()
+        .reporting(report)
+        .unwrap();
+
+    chat.say_choices("which?".to_string(), 2).unwrap();
+    chat.choose(1).unwrap();
+
+    let markdown = fs::read_to_string(&path).unwrap();
+    assert!(markdown.starts_with("# Chat with Sky"));
+    assert!(markdown.contains("## You ("));
+    assert!(markdown.contains("\nwhich?\n"));
+    assert!(markdown.contains("\nfancy\n"));
+    assert!(!markdown.contains("\nplain\n"));
+}
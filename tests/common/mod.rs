@@ -0,0 +1,253 @@
+//! What the integration tests share: a home of its own for sky, and a stand-in for an API.
+
+#![allow(dead_code)]
+
+use std::{
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use serde_json::Value;
+use tempfile::TempDir;
+
+/// The sky binary with a home directory of its own, holding its config and data.
+pub struct Sky {
+    home: TempDir,
+}
+
+impl Sky {
+    /// A home where the config is `config`, in TOML.
+    pub fn new(config: &str) -> Self {
+        let home = tempfile::tempdir().unwrap();
+        let sky = Self { home };
+        fs::create_dir_all(sky.config_dir()).unwrap();
+        fs::create_dir_all(sky.work_dir()).unwrap();
+        fs::write(sky.config_dir().join("default-config.toml"), config).unwrap();
+        sky
+    }
+
+    /// A home where the config has the mock provider answer in `mode`.
+    pub fn mock(mode: &str) -> Self {
+        Self::new(&format!(
+            "provider = 'mock'\nmodel = 'mock'\n\n[mock]\nmode = '{mode}'\npath = 'script.txt'\n"
+        ))
+    }
+
+    pub fn config_dir(&self) -> PathBuf {
+        self.home.path().join("config").join("sky")
+    }
+
+    /// Where sky runs, and where relative paths in the config start from.
+    pub fn work_dir(&self) -> PathBuf {
+        self.home.path().join("work")
+    }
+
+    pub fn write(&self, file: &str, content: &str) -> PathBuf {
+        let path = self.work_dir().join(file);
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    pub fn read(&self, file: impl AsRef<Path>) -> String {
+        fs::read_to_string(self.work_dir().join(file)).unwrap()
+    }
+
+    pub fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_sky"));
+        command
+            .args(args)
+            .current_dir(self.work_dir())
+            .env_clear()
+            .env("PATH", std::env::var("PATH").unwrap_or_default())
+            .env("HOME", self.home.path())
+            .env("XDG_CONFIG_HOME", self.home.path().join("config"))
+            .env("XDG_DATA_HOME", self.home.path().join("data"));
+        command
+    }
+
+    /// Runs sky with `args`, giving it `stdin`.
+    pub fn run(&self, args: &[&str], stdin: &str) -> Run {
+        self.run_with_env(args, stdin, &[])
+    }
+
+    /// Runs sky with `args`, giving it `stdin` and the environment variables in `env`.
+    pub fn run_with_env(&self, args: &[&str], stdin: &str, env: &[(&str, &str)]) -> Run {
+        let mut child = self
+            .command(args)
+            .envs(env.iter().copied())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(stdin.as_bytes())
+            .unwrap();
+        Run(child.wait_with_output().unwrap())
+    }
+}
+
+/// How a run of sky went.
+pub struct Run(pub Output);
+
+impl Run {
+    pub fn stdout(&self) -> String {
+        String::from_utf8_lossy(&self.0.stdout).into_owned()
+    }
+
+    pub fn stderr(&self) -> String {
+        String::from_utf8_lossy(&self.0.stderr).into_owned()
+    }
+
+    pub fn code(&self) -> Option<i32> {
+        self.0.status.code()
+    }
+
+    /// Fails the test, showing what sky said, unless it exited successfully.
+    pub fn success(self) -> Self {
+        assert!(
+            self.0.status.success(),
+            "sky failed with {}\nstdout: {}\nstderr: {}",
+            self.0.status,
+            self.stdout(),
+            self.stderr()
+        );
+        self
+    }
+}
+
+/// What the stand-in sends back for a request.
+pub struct Reply {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Reply {
+    pub fn json(body: Value) -> Self {
+        Self {
+            status: 200,
+            content_type: "application/json",
+            body: body.to_string(),
+        }
+    }
+
+    /// Server-sent events with each of `events` as data.
+    pub fn events(events: &[Value]) -> Self {
+        let mut body: String = events.iter().map(|e| format!("data: {e}\n\n")).collect();
+        body.push_str("data: [DONE]\n\n");
+        Self {
+            status: 200,
+            content_type: "text/event-stream",
+            body,
+        }
+    }
+
+    pub fn status(status: u16, body: Value) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: body.to_string(),
+        }
+    }
+}
+
+/// A request the stand-in received.
+#[derive(Debug, Clone)]
+pub struct Received {
+    pub path: String,
+    /// Header names in lowercase.
+    pub headers: Vec<(String, String)>,
+    pub body: Value,
+}
+
+impl Received {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// A local HTTP server standing in for an API, giving the replies it has in order.
+pub struct StandIn {
+    pub url: String,
+    received: Arc<Mutex<Vec<Received>>>,
+}
+
+impl StandIn {
+    pub fn start(replies: Vec<Reply>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(vec![]));
+
+        let log = received.clone();
+        thread::spawn(move || {
+            for (stream, reply) in listener.incoming().zip(replies) {
+                let Ok(stream) = stream else { break };
+                serve(stream, reply, &log);
+            }
+        });
+
+        Self { url, received }
+    }
+
+    pub fn received(&self) -> Vec<Received> {
+        self.received.lock().unwrap().clone()
+    }
+}
+
+fn serve(mut stream: TcpStream, reply: Reply, log: &Mutex<Vec<Received>>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).unwrap();
+    let path = request_line
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or_default()
+        .to_string();
+
+    let mut headers = vec![];
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let Some((name, value)) = line.trim_end().split_once(':') else {
+            break;
+        };
+        headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+    }
+
+    let length = headers
+        .iter()
+        .find(|(name, _)| name == "content-length")
+        .and_then(|(_, value)| value.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+
+    log.lock().unwrap().push(Received {
+        path,
+        headers,
+        body: serde_json::from_slice(&body).unwrap_or(Value::Null),
+    });
+
+    write!(
+        stream,
+        "HTTP/1.1 {} Stand-in\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        reply.status,
+        reply.content_type,
+        reply.body.len(),
+        reply.body
+    )
+    .unwrap();
+}
@@ -0,0 +1,275 @@
+//! The sky binary end to end, talking HTTP to a local stand-in for the APIs.
+
+mod common;
+
+use common::{Reply, Sky, StandIn};
+use serde_json::json;
+
+/// A home where the config points the `provider` at `api`.
+fn sky_for(api: &StandIn, provider: &str) -> Sky {
+    Sky::new(&format!(
+        "provider = '{provider}'\napi_key = 'sk-test-key'\nbase_url = '{}/v1'\nmodel = 'test-model'\n\n[retry]\nbase_delay_ms = 1\nmax_delay_ms = 1\n",
+        api.url
+    ))
+}
+
+fn openai_answer(text: &str) -> Reply {
+    Reply::events(&[
+        json!({ "choices": [{ "delta": { "content": text }, "finish_reason": null }] }),
+        json!({ "choices": [{ "delta": {}, "finish_reason": "stop" }] }),
+    ])
+}
+
+#[test]
+fn questions_go_out_as_openai_chat_completions() {
+    let api = StandIn::start(vec![openai_answer("Hi from the stand-in")]);
+    let sky = sky_for(&api, "openai");
+
+    let run = sky
+        .run(&["--temperature", "0.2", "ask", "hello"], "")
+        .success();
+
+    assert_eq!(run.stdout(), "Hi from the stand-in\n");
+    let received = api.received();
+    assert_eq!(received.len(), 1);
+    let req = &received[0];
+    assert_eq!(req.path, "/v1/chat/completions");
+    assert_eq!(req.header("authorization"), Some("Bearer sk-test-key"));
+    assert_eq!(req.body["model"], "test-model");
+    assert_eq!(req.body["stream"], true);
+    assert!((req.body["temperature"].as_f64().unwrap() - 0.2).abs() < 1e-6);
+    assert_eq!(req.body["messages"][0]["role"], "system");
+    assert_eq!(req.body["messages"][1]["role"], "user");
+    assert_eq!(req.body["messages"][1]["content"], "hello");
+}
+
+#[test]
+fn the_conversation_so_far_goes_with_every_question() {
+    let api = StandIn::start(vec![
+        openai_answer("Paris"),
+        openai_answer("About 2 million"),
+    ]);
+    let sky = sky_for(&api, "openai");
+
+    sky.run(&[], "capital of France?\nhow many people live there?\n")
+        .success();
+
+    let received = api.received();
+    let messages = received[1].body["messages"].as_array().unwrap().clone();
+    let said: Vec<(&str, &str)> = messages
+        .iter()
+        .map(|m| (m["role"].as_str().unwrap(), m["content"].as_str().unwrap()))
+        .skip(1)
+        .collect();
+    assert_eq!(
+        said,
+        [
+            ("user", "capital of France?"),
+            ("assistant", "Paris"),
+            ("user", "how many people live there?")
+        ]
+    );
+}
+
+#[test]
+fn errors_from_the_api_are_reported() {
+    let api = StandIn::start(vec![Reply::status(
+        401,
+        json!({ "error": { "message": "Incorrect API key provided", "type": "invalid_request_error" } }),
+    )]);
+    let sky = sky_for(&api, "openai");
+
+    let run = sky.run(&["ask", "hello"], "");
+
+    assert_eq!(run.code(), Some(76));
+    assert!(run
+        .stderr()
+        .contains("the API responded with 401: Incorrect API key provided"));
+}
+
+#[test]
+fn failing_servers_are_retried() {
+    let api = StandIn::start(vec![
+        Reply::status(503, json!({})),
+        Reply::status(429, json!({})),
+        openai_answer("third time lucky"),
+    ]);
+    let sky = sky_for(&api, "openai");
+
+    let run = sky.run(&["ask", "hello"], "").success();
+
+    assert_eq!(run.stdout(), "third time lucky\n");
+    assert_eq!(api.received().len(), 3);
+    assert!(run.stderr().contains("(retrying in"));
+}
+
+#[test]
+fn several_choices_are_asked_for_at_once() {
+    let api = StandIn::start(vec![Reply::json(json!({
+        "choices": [
+            { "message": { "role": "assistant", "content": "first" }, "finish_reason": "stop" },
+            { "message": { "role": "assistant", "content": "second" }, "finish_reason": "length" }
+        ],
+        "usage": { "prompt_tokens": 20, "completion_tokens": 4, "total_tokens": 24 }
+    }))]);
+    let sky = sky_for(&api, "openai");
+
+    let run = sky.run(&["--choices", "2", "ask", "hello"], "").success();
+
+    assert_eq!(run.stdout(), "[1] first\n\n[2] second [truncated]\n\n");
+    let req = &api.received()[0];
+    assert_eq!(req.body["n"], 2);
+    assert_eq!(req.body["stream"], false);
+}
+
+#[test]
+fn anthropic_gets_the_system_prompt_on_its_own() {
+    let api = StandIn::start(vec![Reply::events(&[
+        json!({ "type": "message_start", "message": { "usage": { "input_tokens": 12 } } }),
+        json!({ "type": "content_block_delta", "index": 0, "delta": { "type": "text_delta", "text": "Hello" } }),
+        json!({ "type": "content_block_delta", "index": 0, "delta": { "type": "text_delta", "text": " there" } }),
+        json!({ "type": "message_delta", "delta": { "stop_reason": "end_turn" }, "usage": { "output_tokens": 2 } }),
+        json!({ "type": "message_stop" }),
+    ])]);
+    let sky = sky_for(&api, "anthropic");
+
+    let run = sky.run(&["ask", "hi"], "").success();
+
+    assert_eq!(run.stdout(), "Hello there\n");
+    let req = &api.received()[0];
+    assert_eq!(req.path, "/v1/messages");
+    assert_eq!(req.header("x-api-key"), Some("sk-test-key"));
+    assert!(req.header("anthropic-version").is_some());
+    assert!(req.body["system"].as_str().unwrap().contains("Sky"));
+    assert_eq!(
+        req.body["messages"],
+        json!([{ "role": "user", "content": "hi" }])
+    );
+}
+
+#[test]
+fn local_servers_need_no_api_key() {
+    let api = StandIn::start(vec![openai_answer("local answer")]);
+    let sky = Sky::new(&format!(
+        "provider = 'local'\nbase_url = '{}/v1'\nmodel = 'llama3'\n",
+        api.url
+    ));
+
+    let run = sky.run(&["ask", "hello"], "").success();
+
+    assert_eq!(run.stdout(), "local answer\n");
+    assert_eq!(api.received()[0].header("authorization"), None);
+}
+
+#[test]
+fn a_profile_with_its_own_key_goes_before_the_environment() {
+    let api = StandIn::start(vec![openai_answer("personal"), openai_answer("team")]);
+    let sky = Sky::new(&format!(
+        "provider = 'openai'\nbase_url = '{}/v1'\n\n[profiles.team]\napi_key = 'sk-team-key'\n",
+        api.url
+    ));
+    let env = [("OPENAI_API_KEY", "sk-personal-key")];
+
+    sky.run_with_env(&["ask", "hi"], "", &env).success();
+    sky.run_with_env(&["--profile", "team", "ask", "hi"], "", &env)
+        .success();
+
+    let received = api.received();
+    assert_eq!(
+        received[0].header("authorization"),
+        Some("Bearer sk-personal-key")
+    );
+    assert_eq!(
+        received[1].header("authorization"),
+        Some("Bearer sk-team-key")
+    );
+}
+
+#[test]
+fn a_profile_for_another_provider_leaves_the_key_behind() {
+    let api = StandIn::start(vec![openai_answer("local answer")]);
+    let sky = Sky::new(&format!(
+        "provider = 'openai'\napi_key = 'sk-openai-key'\n\n[profiles.home]\nprovider = 'local'\nbase_url = '{}/v1'\n",
+        api.url
+    ));
+
+    sky.run(&["--profile", "home", "ask", "hi"], "").success();
+
+    assert_eq!(api.received()[0].header("authorization"), None);
+}
+
+#[test]
+fn exchanges_are_recorded_and_played_back() {
+    let api = StandIn::start(vec![openai_answer("Paris")]);
+    let sky = sky_for(&api, "openai");
+
+    sky.run(&["--record", "cassette", "ask", "capital of France?"], "")
+        .success();
+
+    let request: serde_json::Value =
+        serde_json::from_str(&sky.read("cassette/0001.request.json")).unwrap();
+    assert!(request["url"]
+        .as_str()
+        .unwrap()
+        .ends_with("/v1/chat/completions"));
+    assert_eq!(request["headers"]["Authorization"], "<redacted>");
+    assert_eq!(
+        request["body"]["messages"][1]["content"],
+        "capital of France?"
+    );
+    assert!(sky.read("cassette/0001.response").contains("\"Paris\""));
+
+    let replayed = Sky::new("provider = 'openai'\nbase_url = 'http://127.0.0.1:9/v1'\n");
+    std::fs::rename(
+        sky.work_dir().join("cassette"),
+        replayed.work_dir().join("cassette"),
+    )
+    .unwrap();
+    let run = replayed
+        .run(&["--replay", "cassette", "ask", "capital of France?"], "")
+        .success();
+    assert_eq!(run.stdout(), "Paris\n");
+    assert_eq!(api.received().len(), 1);
+
+    let run = replayed.run(&["--replay", "cassette"], "one\ntwo\n");
+    assert!(run
+        .stderr()
+        .contains("the cassette in 'cassette' has no response to request 2"));
+}
+
+#[test]
+fn refusals_are_recorded_and_played_back() {
+    let api = StandIn::start(vec![Reply::status(
+        400,
+        json!({ "error": { "message": "context_length_exceeded", "type": "invalid_request_error" } }),
+    )]);
+    let sky = sky_for(&api, "openai");
+
+    let recorded = sky.run(&["--record", "cassette", "ask", "hello"], "");
+    assert_eq!(recorded.code(), Some(76));
+    assert_eq!(sky.read("cassette/0001.status"), "400\n");
+    assert!(sky
+        .read("cassette/0001.response")
+        .contains("context_length_exceeded"));
+
+    let replayed = sky.run(&["--replay", "cassette", "ask", "hello"], "");
+    assert_eq!(replayed.code(), Some(76));
+    assert!(replayed
+        .stderr()
+        .contains("the API responded with 400: context_length_exceeded"));
+    assert_eq!(api.received().len(), 1);
+}
+
+#[test]
+fn recording_carries_on_after_what_is_already_there() {
+    let api = StandIn::start(vec![openai_answer("one"), openai_answer("two")]);
+    let sky = sky_for(&api, "openai");
+
+    sky.run(&["--record", "cassette", "ask", "first"], "")
+        .success();
+    sky.run(&["--record", "cassette", "ask", "second"], "")
+        .success();
+
+    assert!(sky.read("cassette/0001.response").contains("\"one\""));
+    assert!(sky.read("cassette/0002.response").contains("\"two\""));
+}
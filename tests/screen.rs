@@ -0,0 +1,116 @@
+//! The full-screen chat, drawn on a test backend and driven by made-up key presses.
+
+mod common;
+
+use std::{cell::RefCell, io, rc::Rc};
+
+use common::{Reply, StandIn};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+use serde_json::json;
+use sky::{config::Config, provider::ProviderKind, screen::Notices, *};
+use tui::{
+    backend::{Backend, TestBackend},
+    buffer::Cell,
+    layout::Rect,
+    Terminal,
+};
+
+/// A backend keeping the text of every frame drawn on it.
+struct Frames {
+    backend: TestBackend,
+    drawn: Rc<RefCell<Vec<String>>>,
+}
+
+impl Backend for Frames {
+    fn draw<'a, I>(&mut self, content: I) -> io::Result<()>
+    where
+        I: Iterator<Item = (u16, u16, &'a Cell)>,
+    {
+        self.backend.draw(content)
+    }
+
+    fn hide_cursor(&mut self) -> io::Result<()> {
+        self.backend.hide_cursor()
+    }
+
+    fn show_cursor(&mut self) -> io::Result<()> {
+        self.backend.show_cursor()
+    }
+
+    fn get_cursor(&mut self) -> io::Result<(u16, u16)> {
+        self.backend.get_cursor()
+    }
+
+    fn set_cursor(&mut self, x: u16, y: u16) -> io::Result<()> {
+        self.backend.set_cursor(x, y)
+    }
+
+    fn clear(&mut self) -> io::Result<()> {
+        self.backend.clear()
+    }
+
+    fn size(&self) -> io::Result<Rect> {
+        self.backend.size()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let cells = &self.backend.buffer().content;
+        let frame = cells.iter().map(|cell| cell.symbol.as_str()).collect();
+        self.drawn.borrow_mut().push(frame);
+        self.backend.flush()
+    }
+}
+
+/// Typing `text` and sending it, then quitting.
+fn typed(text: &str) -> Vec<io::Result<Event>> {
+    let key = |code| Ok(Event::Key(KeyEvent::new(code, KeyModifiers::NONE)));
+    text.chars()
+        .map(|c| key(KeyCode::Char(c)))
+        .chain([key(KeyCode::Enter), key(KeyCode::Esc)])
+        .collect()
+}
+
+#[test]
+fn retries_show_while_the_answer_is_awaited() {
+    let api = StandIn::start(vec![
+        Reply::status(503, json!({})),
+        Reply::events(&[
+            json!({ "choices": [{ "delta": { "content": "the answer" }, "finish_reason": null }] }),
+            json!({ "choices": [{ "delta": {}, "finish_reason": "stop" }] }),
+        ]),
+    ]);
+    let mut cfg = Config {
+        provider: ProviderKind::Openai,
+        api_key: Some("sk-test-key".to_string()),
+        base_url: Some(format!("{}/v1", api.url)),
+        model: "test-model".to_string(),
+        ..Config::default()
+    };
+    cfg.retry.base_delay_ms = 1;
+    cfg.retry.max_delay_ms = 1;
+    let notices = Notices::default();
+    let retried = notices.clone();
+    let chat = ChatWithAI::new(cfg)
+        .unwrap()
+        .on_retry(move |_| retried.send("retrying soon".to_string()));
+    let drawn = Rc::new(RefCell::new(vec![]));
+    let frames = Frames {
+        backend: TestBackend::new(160, 20),
+        drawn: drawn.clone(),
+    };
+
+    screen::run_on(
+        Terminal::new(frames).unwrap(),
+        Box::new(chat),
+        notices,
+        typed("hello"),
+    )
+    .unwrap();
+
+    let drawn = drawn.borrow();
+    let retrying = drawn.iter().position(|f| f.contains("retrying soon"));
+    let answered = drawn.iter().position(|f| f.contains("the answer"));
+    assert!(retrying.is_some() && answered.is_some(), "{drawn:#?}");
+    assert!(retrying < answered, "{drawn:#?}");
+    assert_eq!(api.received().len(), 2);
+}